@@ -0,0 +1,4 @@
+-- Custom environments have longer names, and so longer key prefixes
+ALTER TABLE api_keys
+    ALTER COLUMN environment TYPE VARCHAR(64),
+    ALTER COLUMN key_prefix TYPE VARCHAR(64);
@@ -0,0 +1,12 @@
+CREATE TABLE api_keys (
+    lookup_id TEXT PRIMARY KEY NOT NULL,
+    key_hash TEXT NOT NULL,
+    environment TEXT NOT NULL,
+    is_active INTEGER NOT NULL,
+    is_revoked INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    last_used_at TEXT,
+    expires_at TEXT
+);
+
+CREATE INDEX idx_api_keys_environment ON api_keys (environment);
@@ -0,0 +1 @@
+ALTER TABLE api_keys ADD COLUMN ip_policy TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,2 @@
+-- Keys created before scopes existed keep full access
+ALTER TABLE api_keys ADD COLUMN scopes TEXT NOT NULL DEFAULT '["keys:read","keys:write","audit:read"]';
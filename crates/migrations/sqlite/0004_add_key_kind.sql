@@ -0,0 +1,3 @@
+-- Keys created before publishable keys existed are all secret keys
+ALTER TABLE api_keys ADD COLUMN kind TEXT NOT NULL DEFAULT 'secret';
+ALTER TABLE api_keys ADD COLUMN paired_key TEXT;
@@ -0,0 +1,3 @@
+-- Usage is flushed in batches by UsageTracker, so these lag behind by up to one flush
+ALTER TABLE api_keys ADD COLUMN request_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE api_keys ADD COLUMN last_used_ip TEXT;
@@ -0,0 +1,82 @@
+use chrono::{Duration, Utc};
+use crate::authorization::Scope;
+use crate::error::{ApiKeyError, Result};
+use crate::generation::{Environment, KeyFormat, KeyKind, ParsedApiKey};
+use crate::storage::ApiKeyStorage;
+use crate::validation::{validate_verified_api_key, ApiKeyMetadata};
+
+/// A key that passed [`authenticate`]
+#[derive(Debug, Clone)]
+pub struct AuthenticatedKey {
+    /// Identifies the key in storage and audit logs without revealing it
+    pub lookup_id: String,
+    pub kind: KeyKind,
+    pub environment: Environment,
+    pub scopes: Vec<Scope>,
+    /// Time left before the key expires, or `None` if it never does
+    pub expires_in: Option<Duration>,
+    /// The key's metadata, for [`authorize`](crate::authorization::authorize) and request checks
+    pub metadata: ApiKeyMetadata,
+}
+
+/// Authenticates a default-format key against `storage`
+///
+/// Parses the key, looks it up (which verifies its hash exactly once), and
+/// checks that it is active, unrevoked and unexpired.
+///
+/// The use is not recorded: nothing is written to storage. To track usage,
+/// authenticate through [`UsageTracker::authenticate`](crate::UsageTracker::authenticate),
+/// which batches those writes.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main]
+/// # async fn main() -> tronch::Result<()> {
+/// use tronch::{authenticate, generate_api_key, ApiKeyError, ApiKeyStorage, Environment, InMemoryStorage};
+///
+/// let storage = InMemoryStorage::new();
+/// let (key, metadata) = generate_api_key(Environment::Test).unwrap();
+/// storage.store_key(&key, metadata).await?;
+///
+/// let authenticated = authenticate(&storage, key.expose_secret()).await?;
+/// assert_eq!(authenticated.environment, Environment::Test);
+/// assert_eq!(authenticate(&storage, "not a key").await.unwrap_err(), ApiKeyError::InvalidFormat);
+/// # Ok(())
+/// # }
+/// ```
+pub async fn authenticate<S>(storage: &S, key: &str) -> Result<AuthenticatedKey>
+where
+    S: ApiKeyStorage + ?Sized,
+{
+    authenticate_with_format(storage, key, &KeyFormat::default()).await
+}
+
+/// Authenticates a key issued in a custom [`KeyFormat`]
+pub async fn authenticate_with_format<S>(storage: &S, key: &str, format: &KeyFormat) -> Result<AuthenticatedKey>
+where
+    S: ApiKeyStorage + ?Sized,
+{
+    let parsed = format.parse(key).map_err(|_| ApiKeyError::InvalidFormat)?;
+    authenticate_parsed(storage, &parsed).await
+}
+
+/// Authenticates a key that has already been parsed
+///
+/// This is how keys imported from the legacy store authenticate, since no
+/// [`KeyFormat`] parses them: wrap them with [`ParsedApiKey::legacy`] first.
+pub async fn authenticate_parsed<S>(storage: &S, key: &ParsedApiKey) -> Result<AuthenticatedKey>
+where
+    S: ApiKeyStorage + ?Sized,
+{
+    let metadata = storage.get_metadata(key).await?;
+    validate_verified_api_key(key, &metadata)?;
+
+    Ok(AuthenticatedKey {
+        lookup_id: key.lookup_id().to_string(),
+        kind: metadata.kind,
+        environment: metadata.environment,
+        scopes: metadata.scopes.clone(),
+        expires_in: metadata.expires_at.map(|expires_at| (expires_at - Utc::now()).max(Duration::zero())),
+        metadata,
+    })
+}
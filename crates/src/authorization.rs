@@ -0,0 +1,88 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::generation::KeyKind;
+use crate::validation::ApiKeyMetadata;
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum AuthorizationError {
+    #[error("Insufficient scope: {0} required")]
+    InsufficientScope(Scope),
+    #[error("Unknown scope: {0}")]
+    UnknownScope(String),
+}
+
+/// A permission granted to an API key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "keys:read")]
+    KeysRead,
+    #[serde(rename = "keys:write")]
+    KeysWrite,
+    #[serde(rename = "audit:read")]
+    AuditRead,
+    #[serde(rename = "public:read")]
+    PublicRead,
+}
+
+impl Scope {
+    /// Every scope a secret key can hold; they get these unless generated with a narrower set
+    pub fn all() -> Vec<Scope> {
+        vec![Scope::KeysRead, Scope::KeysWrite, Scope::AuditRead]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::KeysRead => "keys:read",
+            Scope::KeysWrite => "keys:write",
+            Scope::AuditRead => "audit:read",
+            Scope::PublicRead => "public:read",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<&str> for Scope {
+    type Error = AuthorizationError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Scope::all()
+            .into_iter()
+            .chain([Scope::PublicRead])
+            .find(|scope| scope.as_str() == value)
+            .ok_or_else(|| AuthorizationError::UnknownScope(value.to_string()))
+    }
+}
+
+/// Checks that a key has been granted `required`
+///
+/// Secret keys may always do what a publishable key can, so `public:read`
+/// is implied for them.
+///
+/// # Examples
+/// ```
+/// use tronch::authorization::{authorize, Scope};
+/// use tronch::generation::{generate_api_key_with_scopes, Environment};
+///
+/// let (_, metadata) = generate_api_key_with_scopes(Environment::Test, vec![Scope::KeysRead]).unwrap();
+/// assert!(authorize(&metadata, Scope::KeysRead).is_ok());
+/// assert!(authorize(&metadata, Scope::KeysWrite).is_err());
+/// ```
+pub fn authorize(metadata: &ApiKeyMetadata, required: Scope) -> Result<(), AuthorizationError> {
+    let implied = required == Scope::PublicRead && metadata.kind == KeyKind::Secret;
+    if implied || metadata.scopes.contains(&required) {
+        Ok(())
+    } else {
+        Err(AuthorizationError::InsufficientScope(required))
+    }
+}
+
+/// Checks that a key has been granted every scope in `required`
+pub fn authorize_all(metadata: &ApiKeyMetadata, required: &[Scope]) -> Result<(), AuthorizationError> {
+    required.iter().try_for_each(|scope| authorize(metadata, *scope))
+}
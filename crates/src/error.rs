@@ -2,6 +2,8 @@ use thiserror::Error;
 use serde::{Serialize, Deserialize};
 
 use crate::request::RequestValidationError;
+use crate::storage::StorageError as KeyStorageError;
+use crate::validation::ApiKeyValidationError;
 
 #[derive(Debug, Error, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ApiKeyError {
@@ -13,6 +15,8 @@ pub enum ApiKeyError {
     Revoked,
     #[error("API key is expired")]
     Expired,
+    #[error("API key is inactive")]
+    Inactive,
     #[error("Invalid environment: {0}")]
     InvalidEnvironment(String),
     #[error("Request validation error: {0}")]
@@ -33,6 +37,34 @@ pub enum ApiKeyError {
     Validation(#[from] ValidationError),
 }
 
+impl From<KeyStorageError> for ApiKeyError {
+    fn from(err: KeyStorageError) -> Self {
+        match err {
+            KeyStorageError::KeyNotFound => ApiKeyError::NotFound,
+            KeyStorageError::Backend(e) => ApiKeyError::Storage(e),
+            KeyStorageError::StorageError(e) => ApiKeyError::Storage(StorageError::QueryError(e)),
+            KeyStorageError::KeyExists | KeyStorageError::HashError(_) => ApiKeyError::Internal(err.to_string()),
+        }
+    }
+}
+
+impl From<ApiKeyValidationError> for ApiKeyError {
+    fn from(err: ApiKeyValidationError) -> Self {
+        match err {
+            ApiKeyValidationError::InvalidFormat => ApiKeyError::InvalidFormat,
+            ApiKeyValidationError::KeyNotFound => ApiKeyError::NotFound,
+            ApiKeyValidationError::KeyExpired => ApiKeyError::Expired,
+            ApiKeyValidationError::KeyRevoked => ApiKeyError::Revoked,
+            ApiKeyValidationError::KeyInactive => ApiKeyError::Inactive,
+            ApiKeyValidationError::EnvironmentMismatch => {
+                ApiKeyError::InvalidEnvironment("key does not match its stored environment".to_string())
+            }
+            ApiKeyValidationError::InvalidTimestamp => ApiKeyError::Validation(ValidationError::InvalidTimestamp),
+            ApiKeyValidationError::HashVerificationFailed => ApiKeyError::Hashing(HashingError::VerificationFailed),
+        }
+    }
+}
+
 #[derive(Debug, Error, Clone, Serialize, Deserialize, PartialEq)]
 pub enum HashingError {
     #[error("Failed to generate salt")]
@@ -57,6 +89,22 @@ pub enum StorageError {
     MigrationError(String),
 }
 
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::Configuration(_)
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed => StorageError::ConnectionError(err.to_string()),
+            sqlx::Error::Migrate(_) => StorageError::MigrationError(err.to_string()),
+            _ => StorageError::QueryError(err.to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Error, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ConfigurationError {
     #[error("Missing required configuration: {0}")]
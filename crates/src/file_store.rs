@@ -0,0 +1,266 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::Deserialize;
+use crate::generation::{Environment, LegacyKeys, ParsedApiKey};
+use crate::hashing::{Argon2Hasher, KeyHasher};
+use crate::storage::{apply_usage, keep_usage, verify_found, ApiKeyStorage, StorageError};
+use crate::usage::KeyUsage;
+use crate::validation::ApiKeyMetadata;
+
+/// Keys as persisted on disk, indexed by lookup id
+type KeyMap = BTreeMap<String, ApiKeyMetadata>;
+
+/// A record from the legacy store.json, keyed by key id
+#[derive(Debug, Deserialize)]
+struct LegacyRecord {
+    hashed_api_key: String,
+}
+
+/// File-backed storage implementation that keeps every key in one JSON file
+///
+/// Writes go to a temporary file that is renamed over the store, so readers
+/// never see a partial write, and a sibling `.lock` file serializes access
+/// between processes.
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    path: PathBuf,
+    hasher: Arc<dyn KeyHasher>,
+}
+
+impl FileStorage {
+    /// Uses the JSON file at `path`, which is created on the first write
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            hasher: Arc::new(Argon2Hasher::default()),
+        }
+    }
+
+    /// Verifies keys with `hasher`, upgrading other hashes to it as their keys are looked up
+    pub fn with_hasher(mut self, hasher: Arc<dyn KeyHasher>) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Imports the legacy store.json format into this store
+    ///
+    /// Legacy entries only kept a SHA-256 digest of each key, so they are
+    /// verified against that digest, and indexed on the keyed id `legacy`
+    /// derives from it. To look up or authenticate an imported key, wrap it
+    /// with [`ParsedApiKey::legacy`] under the same `legacy` and pass it to
+    /// [`authenticate_parsed`](crate::authenticate_parsed) or
+    /// [`validate_parsed_api_key`](crate::validate_parsed_api_key); the
+    /// string-taking entry points only accept formatted keys. Entries already
+    /// present are skipped, which makes re-running the import harmless.
+    ///
+    /// # Returns
+    /// * `Result<usize, StorageError>` - The number of keys imported
+    pub async fn import_legacy(
+        &self,
+        legacy_path: impl AsRef<Path>,
+        legacy: &LegacyKeys,
+    ) -> Result<usize, StorageError> {
+        let contents = fs::read_to_string(legacy_path).map_err(io_error)?;
+        let entries: HashMap<String, LegacyRecord> = serde_json::from_str(&contents)
+            .map_err(|e| StorageError::StorageError(format!("Invalid legacy store: {}", e)))?;
+
+        let records = entries
+            .values()
+            .map(|record| ApiKeyMetadata::from_legacy_digest(legacy, &record.hashed_api_key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.modify(move |keys| {
+            let mut imported = 0;
+            for metadata in records {
+                if !keys.contains_key(&metadata.lookup_id) {
+                    keys.insert(metadata.lookup_id.clone(), metadata);
+                    imported += 1;
+                }
+            }
+            Ok(imported)
+        })
+        .await
+    }
+
+    /// Reads the store under a shared lock
+    async fn read(&self) -> Result<KeyMap, StorageError> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let lock = lock_file(&path)?;
+            lock.lock_shared().map_err(io_error)?;
+            load(&path)
+        })
+        .await
+        .map_err(|e| StorageError::StorageError(e.to_string()))?
+    }
+
+    /// Applies `f` to the store under an exclusive lock, persisting the result atomically
+    async fn modify<F, T>(&self, f: F) -> Result<T, StorageError>
+    where
+        F: FnOnce(&mut KeyMap) -> Result<T, StorageError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let lock = lock_file(&path)?;
+            lock.lock().map_err(io_error)?;
+
+            let mut keys = load(&path)?;
+            let result = f(&mut keys)?;
+            save(&path, &keys)?;
+            Ok(result)
+        })
+        .await
+        .map_err(|e| StorageError::StorageError(e.to_string()))?
+    }
+
+    async fn find(&self, key: &ParsedApiKey) -> Result<Option<(String, ApiKeyMetadata)>, StorageError> {
+        let lookup_id = key.lookup_id().to_string();
+        let found = self.read().await?.remove(&lookup_id);
+        Ok(verify_found(found, key, &*self.hasher)?.map(|metadata| (lookup_id, metadata)))
+    }
+}
+
+/// Returns the metadata `key` matches in `keys`, read under the store's lock
+fn verified(keys: &KeyMap, key: &ParsedApiKey, hasher: &dyn KeyHasher) -> Result<ApiKeyMetadata, StorageError> {
+    let found = keys.get(key.lookup_id()).cloned();
+    verify_found(found, key, hasher)?.ok_or(StorageError::KeyNotFound)
+}
+
+fn io_error(err: std::io::Error) -> StorageError {
+    StorageError::StorageError(err.to_string())
+}
+
+fn lock_file(path: &Path) -> Result<File, StorageError> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)
+        .map_err(io_error)
+}
+
+fn load(path: &Path) -> Result<KeyMap, StorageError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| StorageError::StorageError(format!("Corrupt key store: {}", e))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(KeyMap::new()),
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+fn save(path: &Path, keys: &KeyMap) -> Result<(), StorageError> {
+    let contents = serde_json::to_vec_pretty(keys)
+        .map_err(|e| StorageError::StorageError(e.to_string()))?;
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut tmp = File::create(&tmp_path).map_err(io_error)?;
+    tmp.write_all(&contents).map_err(io_error)?;
+    tmp.sync_all().map_err(io_error)?;
+    fs::rename(&tmp_path, path).map_err(io_error)
+}
+
+#[async_trait::async_trait]
+impl ApiKeyStorage for FileStorage {
+    async fn store_key(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
+        let lookup_id = key.lookup_id().to_string();
+        self.modify(move |keys| {
+            if keys.contains_key(&lookup_id) {
+                return Err(StorageError::KeyExists);
+            }
+            keys.insert(lookup_id, metadata);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_metadata(&self, key: &ParsedApiKey) -> Result<ApiKeyMetadata, StorageError> {
+        let (lookup_id, mut metadata) = match self.find(key).await? {
+            Some(found) => found,
+            None => return Err(StorageError::KeyNotFound),
+        };
+
+        let old_hash = metadata.key_hash.clone();
+        if metadata.rehash_if_needed(key.expose_secret(), &*self.hasher)? {
+            let new_hash = metadata.key_hash.clone();
+            self.modify(move |keys| {
+                // Leave the entry alone if it was changed since we read it
+                if let Some(entry) = keys.get_mut(&lookup_id) {
+                    if entry.key_hash == old_hash {
+                        entry.key_hash = new_hash;
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(metadata)
+    }
+
+    async fn update_metadata(&self, key: &ParsedApiKey, mut metadata: ApiKeyMetadata) -> Result<(), StorageError> {
+        // Verify and write under one lock, so a concurrent delete or rotation can't slip in between
+        let key = key.clone();
+        let hasher = self.hasher.clone();
+        self.modify(move |keys| {
+            let stored = verified(keys, &key, &*hasher)?;
+            keep_usage(&mut metadata, &stored);
+            keys.insert(key.lookup_id().to_string(), metadata);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_key(&self, key: &ParsedApiKey) -> Result<(), StorageError> {
+        let key = key.clone();
+        let hasher = self.hasher.clone();
+        self.modify(move |keys| {
+            verified(keys, &key, &*hasher)?;
+            keys.remove(key.lookup_id());
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_keys(&self, environment: Environment) -> Result<Vec<String>, StorageError> {
+        Ok(self
+            .read()
+            .await?
+            .into_iter()
+            .filter(|(_, metadata)| metadata.environment == environment)
+            .map(|(lookup_id, _)| lookup_id)
+            .collect())
+    }
+
+    async fn record_usage(&self, usage: &[KeyUsage]) -> Result<(), StorageError> {
+        let usage = usage.to_vec();
+        self.modify(move |keys| {
+            for usage in &usage {
+                if let Some(metadata) = keys.get_mut(&usage.lookup_id) {
+                    apply_usage(metadata, usage);
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_paired_key(&self, lookup_id: &str, paired_key: Option<&str>) -> Result<(), StorageError> {
+        let lookup_id = lookup_id.to_string();
+        let paired_key = paired_key.map(str::to_string);
+        self.modify(move |keys| match keys.get_mut(&lookup_id) {
+            Some(metadata) => {
+                metadata.paired_key = paired_key;
+                Ok(())
+            }
+            None => Err(StorageError::KeyNotFound),
+        })
+        .await
+    }
+}
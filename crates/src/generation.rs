@@ -1,9 +1,33 @@
-use rand::{distributions::Alphanumeric, Rng};
+use rand::rngs::ThreadRng;
+use rand::{CryptoRng, Rng, RngCore};
 use thiserror::Error;
-use std::time::{SystemTime, UNIX_EPOCH};
-use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use chrono::DateTime;
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::authorization::Scope;
 use crate::validation::ApiKeyMetadata;
-use crate::hashing::HashingError;
+use crate::hashing::{hmac_sha256_hex, sha256_hex, Argon2Hasher, HashingError, KeyHasher, MIN_PEPPER_LEN};
+use crate::rate_limit::{SystemTimeProvider, TimeProvider};
+use crate::secret::SecretApiKey;
+use zeroize::Zeroizing;
+
+/// Length of the lookup id carried by generated keys: the 8-char timestamp
+/// segment followed by the first 8 random characters.
+pub const LOOKUP_ID_LEN: usize = 16;
+
+/// Length of the base62 CRC32 checksum that ends every generated key
+pub const CHECKSUM_LEN: usize = 6;
+
+/// Least entropy a key format may leave in the secret part of a key
+pub const MIN_SECRET_BITS: f64 = 80.0;
+
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const CROCKFORD_BASE32: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
 
 #[derive(Error, Debug)]
 pub enum KeyGenerationError {
@@ -13,78 +37,274 @@ pub enum KeyGenerationError {
     GenerationFailed,
     #[error("Invalid key format")]
     InvalidFormat,
+    #[error("Invalid key checksum")]
+    InvalidChecksum,
+    #[error("Unsafe key format: {0}")]
+    UnsafeFormat(String),
     #[error("Failed to hash key: {0}")]
     HashingError(#[from] HashingError),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// Longest name a custom environment may have
+pub const MAX_ENVIRONMENT_LEN: usize = 32;
+
+/// Longest brand a [`KeyFormat`] may have, so that a key's prefix fits in 64
+/// characters with the longest environment name
+pub const MAX_BRAND_LEN: usize = 24;
+
+/// Length of the `test` and `live` names that [`KeyFormat::length`] is measured with
+const BUILTIN_ENVIRONMENT_LEN: usize = 4;
+
+lazy_static! {
+    /// Names of the environments registered with [`Environment::register`]
+    static ref CUSTOM_ENVIRONMENTS: RwLock<Vec<&'static str>> = RwLock::new(Vec::new());
+}
+
+/// The environment a key belongs to
+///
+/// Besides `Test` and `Live`, deployments can register their own
+/// environments (staging, sandbox, per-region...) at startup. A custom
+/// environment's name is used as-is in its keys' prefixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Environment {
     Test,
     Live,
+    Custom(CustomEnvironment),
+}
+
+/// Name of an environment registered with [`Environment::register`]
+///
+/// Only `register` makes these, so every custom environment is a registered
+/// one, and comes back unchanged from its name.
+///
+/// ```compile_fail
+/// use tronch::generation::{CustomEnvironment, Environment};
+///
+/// let shadow = Environment::Custom(CustomEnvironment("test"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomEnvironment(&'static str);
+
+impl CustomEnvironment {
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
 }
 
 impl Environment {
-    pub fn prefix(&self) -> &'static str {
+    /// Registers a custom environment, returning it
+    ///
+    /// Names must be lowercase ASCII letters, digits or `-`, at most
+    /// [`MAX_ENVIRONMENT_LEN`] long. Registering a name twice is harmless.
+    ///
+    /// # Examples
+    /// ```
+    /// use tronch::generation::{generate_api_key, Environment};
+    ///
+    /// let staging = Environment::register("staging").unwrap();
+    /// assert_eq!(Environment::try_from("staging").unwrap(), staging);
+    ///
+    /// let (key, _) = generate_api_key(staging).unwrap();
+    /// assert!(key.expose_secret().starts_with("tronch_sk_staging_"));
+    /// ```
+    pub fn register(name: &str) -> Result<Environment, KeyGenerationError> {
+        let valid = !name.is_empty()
+            && name.len() <= MAX_ENVIRONMENT_LEN
+            && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            && name != Environment::Test.as_str()
+            && name != Environment::Live.as_str();
+        if !valid {
+            return Err(KeyGenerationError::InvalidEnvironment);
+        }
+
+        let mut registered = CUSTOM_ENVIRONMENTS.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(existing) = registered.iter().find(|existing| **existing == name) {
+            return Ok(Environment::Custom(CustomEnvironment(existing)));
+        }
+
+        // Registered names live for the rest of the process, like the built-in ones
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        registered.push(name);
+        Ok(Environment::Custom(CustomEnvironment(name)))
+    }
+
+    /// Returns `Test`, `Live` and every registered custom environment
+    pub fn all() -> Vec<Environment> {
+        let registered = CUSTOM_ENVIRONMENTS.read().unwrap_or_else(|e| e.into_inner());
+        [Environment::Test, Environment::Live]
+            .into_iter()
+            .chain(registered.iter().map(|name| Environment::Custom(CustomEnvironment(name))))
+            .collect()
+    }
+
+    /// Returns the default-format prefix of secret keys in this environment
+    pub fn prefix(&self) -> String {
+        KeyKind::Secret.prefix(*self)
+    }
+
+    /// Returns the lowercase name used when persisting the environment
+    pub fn as_str(&self) -> &'static str {
         match self {
-            Environment::Test => "tronch_sk_test_",
-            Environment::Live => "tronch_sk_live_",
+            Environment::Test => "test",
+            Environment::Live => "live",
+            Environment::Custom(name) => name.as_str(),
         }
     }
 }
 
-impl TryFrom<&str> for Environment {
+// `Test` and `Live` keep the variant names they were always stored under
+impl Serialize for Environment {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Environment::Test => serializer.serialize_str("Test"),
+            Environment::Live => serializer.serialize_str("Live"),
+            Environment::Custom(name) => serializer.serialize_str(name.as_str()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Environment {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Environment::try_from(name.as_str())
+            .map_err(|_| D::Error::custom(format!("unregistered environment: {}", name)))
+    }
+}
+
+/// Distinguishes secret keys from publishable keys that are safe to embed in browser code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyKind {
+    #[default]
+    Secret,
+    Publishable,
+}
+
+impl KeyKind {
+    /// Returns the tag identifying the kind within a key's prefix
+    pub fn tag(&self) -> &'static str {
+        match self {
+            KeyKind::Secret => "sk",
+            KeyKind::Publishable => "pk",
+        }
+    }
+
+    /// Returns the default-format key prefix for this kind in an environment, e.g. `tronch_pk_live_`
+    pub fn prefix(&self, env: Environment) -> String {
+        KeyFormat::default().prefix(*self, env)
+    }
+
+    /// Returns the lowercase name used when persisting the kind
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyKind::Secret => "secret",
+            KeyKind::Publishable => "publishable",
+        }
+    }
+
+    /// Scopes a new key of this kind is granted unless told otherwise
+    pub fn default_scopes(&self) -> Vec<Scope> {
+        match self {
+            KeyKind::Secret => Scope::all(),
+            KeyKind::Publishable => vec![Scope::PublicRead],
+        }
+    }
+}
+
+impl TryFrom<&str> for KeyKind {
     type Error = KeyGenerationError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value.to_lowercase().as_str() {
-            "test" => Ok(Environment::Test),
-            "live" => Ok(Environment::Live),
-            _ => Err(KeyGenerationError::InvalidEnvironment),
+        match value {
+            "secret" => Ok(KeyKind::Secret),
+            "publishable" => Ok(KeyKind::Publishable),
+            _ => Err(KeyGenerationError::InvalidFormat),
         }
     }
 }
 
-/// Generates a new API key with the specified environment prefix and returns both the key and its metadata.
+/// A secret key and the publishable key paired with it
+#[derive(Debug, Clone)]
+pub struct KeyPair {
+    pub secret_key: ParsedApiKey,
+    pub secret_metadata: ApiKeyMetadata,
+    pub publishable_key: ParsedApiKey,
+    pub publishable_metadata: ApiKeyMetadata,
+}
+
+impl TryFrom<&str> for Environment {
+    type Error = KeyGenerationError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let name = value.to_lowercase();
+        Environment::all()
+            .into_iter()
+            .find(|env| env.as_str() == name)
+            .ok_or(KeyGenerationError::InvalidEnvironment)
+    }
+}
+
+/// Generates a new secret API key with the specified environment prefix and returns both the key and its metadata.
 /// 
 /// # Examples
 /// ```
 /// use tronch::generation::{generate_api_key, Environment};
 /// 
 /// let (api_key, metadata) = generate_api_key(Environment::Test).unwrap();
-/// assert!(api_key.starts_with("tronch_sk_test_"));
-/// ```
-pub fn generate_api_key(env: Environment) -> Result<(String, ApiKeyMetadata), KeyGenerationError> {
-    // Generate a timestamp component (8 chars)
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|_| KeyGenerationError::GenerationFailed)?
-        .as_secs()
-        .to_string()
-        .chars()
-        .rev()
-        .take(8)
-        .collect::<String>();
-
-    // Calculate remaining length for random component
-    let prefix_len = env.prefix().len();
-    let random_len = 52 - prefix_len - 8; // Total length - prefix - timestamp
-
-    // Generate a random component
-    let random: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(random_len)
-        .map(char::from)
-        .collect();
-
-    let key = format!("{}{}{}", env.prefix(), timestamp, random);
-    
-    // Validate the generated key
-    validate_key_format(&key, None)?;
-    
-    // Create metadata with hash
-    let metadata = ApiKeyMetadata::new(env, &key)?;
-    
-    Ok((key, metadata))
+/// assert!(api_key.expose_secret().starts_with("tronch_sk_test_"));
+/// ```
+pub fn generate_api_key(env: Environment) -> Result<(ParsedApiKey, ApiKeyMetadata), KeyGenerationError> {
+    generate_api_key_with_scopes(env, Scope::all())
+}
+
+/// Generates a new API key that is only granted `scopes`
+///
+/// # Examples
+/// ```
+/// use tronch::authorization::Scope;
+/// use tronch::generation::{generate_api_key_with_scopes, Environment};
+///
+/// let (_, metadata) = generate_api_key_with_scopes(Environment::Live, vec![Scope::KeysRead]).unwrap();
+/// assert_eq!(metadata.scopes, vec![Scope::KeysRead]);
+/// ```
+pub fn generate_api_key_with_scopes(
+    env: Environment,
+    scopes: Vec<Scope>,
+) -> Result<(ParsedApiKey, ApiKeyMetadata), KeyGenerationError> {
+    generate_key(KeyKind::Secret, env, scopes)
+}
+
+/// Generates a publishable key with the limited scopes publishable keys get by default
+pub fn generate_publishable_key(env: Environment) -> Result<(ParsedApiKey, ApiKeyMetadata), KeyGenerationError> {
+    generate_key(KeyKind::Publishable, env, KeyKind::Publishable.default_scopes())
+}
+
+/// Generates a secret key together with a publishable key, each recording the other's lookup id
+///
+/// # Examples
+/// ```
+/// use tronch::generation::{generate_key_pair, Environment};
+///
+/// let pair = generate_key_pair(Environment::Live).unwrap();
+/// assert!(pair.publishable_key.expose_secret().starts_with("tronch_pk_live_"));
+/// assert_eq!(pair.publishable_metadata.paired_key, Some(pair.secret_metadata.lookup_id.clone()));
+/// ```
+pub fn generate_key_pair(env: Environment) -> Result<KeyPair, KeyGenerationError> {
+    KeyGenerator::default().generate_key_pair(env)
+}
+
+/// Generates a key of any kind with explicit scopes, in the default tronch format
+pub fn generate_key(
+    kind: KeyKind,
+    env: Environment,
+    scopes: Vec<Scope>,
+) -> Result<(ParsedApiKey, ApiKeyMetadata), KeyGenerationError> {
+    KeyFormat::default().generate(kind, env, scopes)
+}
+
+/// Returns the kind and environment named by a key's tronch prefix
+pub fn parse_prefix(key: &str) -> Option<(KeyKind, Environment)> {
+    KeyFormat::default().parse_prefix(key)
 }
 
 /// Validates the format of an API key
@@ -96,37 +316,576 @@ pub fn generate_api_key(env: Environment) -> Result<(String, ApiKeyMetadata), Ke
 /// # Returns
 /// * `Result<(), KeyGenerationError>` - Ok if valid, error if invalid
 pub fn validate_key_format(key: &str, expected_env: Option<Environment>) -> Result<(), KeyGenerationError> {
-    // Check if key starts with valid prefix
-    let env = if key.starts_with("tronch_sk_test_") {
-        Environment::Test
-    } else if key.starts_with("tronch_sk_live_") {
-        Environment::Live
-    } else {
-        return Err(KeyGenerationError::InvalidFormat);
-    };
-
-    // Check environment match if provided
-    if let Some(expected) = expected_env {
-        if env != expected {
+    KeyFormat::default().validate_key(key, expected_env)
+}
+
+/// Computes the checksum segment for everything in a default-format key that precedes it
+///
+/// This is the CRC32 of the body, base62-encoded and zero-padded to
+/// [`CHECKSUM_LEN`] characters.
+pub fn checksum(body: &str) -> String {
+    KeyAlphabet::Alphanumeric.checksum(body)
+}
+
+/// The characters a key's random segment and checksum are drawn from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyAlphabet {
+    /// `0-9`, `A-Z` and `a-z`
+    #[default]
+    Alphanumeric,
+    /// Crockford's base32: digits and upper-case letters, without `I`, `L`, `O` and `U`
+    CrockfordBase32,
+}
+
+impl KeyAlphabet {
+    /// Returns the alphabet's characters in ascending digit order
+    pub fn chars(&self) -> &'static [u8] {
+        match self {
+            KeyAlphabet::Alphanumeric => BASE62,
+            KeyAlphabet::CrockfordBase32 => CROCKFORD_BASE32,
+        }
+    }
+
+    /// Bits of entropy carried by one uniformly random character
+    pub fn bits_per_char(&self) -> f64 {
+        (self.chars().len() as f64).log2()
+    }
+
+    /// Number of characters needed to hold a CRC32 in this alphabet
+    pub fn checksum_len(&self) -> usize {
+        match self {
+            KeyAlphabet::Alphanumeric => CHECKSUM_LEN,
+            KeyAlphabet::CrockfordBase32 => 7,
+        }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        c.is_ascii() && self.chars().contains(&(c as u8))
+    }
+
+    /// Encodes the CRC32 of `body` in this alphabet, zero-padded to [`Self::checksum_len`]
+    pub fn checksum(&self, body: &str) -> String {
+        let chars = self.chars();
+        let base = chars.len() as u32;
+        let mut value = crc32fast::hash(body.as_bytes());
+        let mut digits = vec![chars[0]; self.checksum_len()];
+        for digit in digits.iter_mut().rev() {
+            *digit = chars[(value % base) as usize];
+            value /= base;
+        }
+        digits.into_iter().map(char::from).collect()
+    }
+}
+
+/// Shape of generated keys: `{brand}_{sk|pk}_{env}_`, then an 8-digit
+/// timestamp, a random segment and a checksum, padded out to `length`.
+///
+/// The first 8 random characters are part of the public lookup id, so only
+/// the rest of the random segment counts as secret; formats that leave it
+/// with fewer than [`MIN_SECRET_BITS`] are rejected.
+///
+/// The default is 66 characters, which leaves the same 29 secret base62
+/// characters (about 172 bits) as the original 52-character keys, which
+/// had neither a lookup id nor a checksum.
+///
+/// # Examples
+/// ```
+/// use tronch::generation::{Environment, KeyAlphabet, KeyFormat, KeyKind};
+///
+/// let format = KeyFormat::new("acme").with_length(60).with_alphabet(KeyAlphabet::CrockfordBase32);
+/// let (key, _) = format.generate(KeyKind::Secret, Environment::Live, vec![]).unwrap();
+/// assert!(key.expose_secret().starts_with("acme_sk_live_"));
+/// assert!(format.validate_key(key.expose_secret(), Some(Environment::Live)).is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFormat {
+    /// Leading segment of every key, without the trailing underscore
+    pub brand: String,
+    /// Total length of a key, including its prefix
+    ///
+    /// Environments named with more than four characters lengthen their
+    /// keys by the difference, so every environment keeps the same secret.
+    pub length: usize,
+    /// Alphabet of the random segment and checksum
+    pub alphabet: KeyAlphabet,
+}
+
+impl Default for KeyFormat {
+    fn default() -> Self {
+        Self {
+            brand: "tronch".to_string(),
+            length: 66,
+            alphabet: KeyAlphabet::Alphanumeric,
+        }
+    }
+}
+
+impl KeyFormat {
+    /// The default format under another brand
+    pub fn new(brand: impl Into<String>) -> Self {
+        Self {
+            brand: brand.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_length(mut self, length: usize) -> Self {
+        self.length = length;
+        self
+    }
+
+    pub fn with_alphabet(mut self, alphabet: KeyAlphabet) -> Self {
+        self.alphabet = alphabet;
+        self
+    }
+
+    /// Returns the prefix of keys of `kind` in `env`, e.g. `tronch_pk_live_`
+    pub fn prefix(&self, kind: KeyKind, env: Environment) -> String {
+        format!("{}_{}_{}_", self.brand, kind.tag(), env.as_str())
+    }
+
+    /// Returns the total length of keys in `env`
+    pub fn key_len(&self, env: Environment) -> usize {
+        self.length + env.as_str().len().saturating_sub(BUILTIN_ENVIRONMENT_LEN)
+    }
+
+    /// Bits of entropy in the secret part of a key, which is the same in every environment
+    pub fn secret_bits(&self) -> f64 {
+        let random_len = self.random_len(KeyKind::Secret, Environment::Test).unwrap_or(0);
+        random_len.saturating_sub(LOOKUP_ID_LEN - 8) as f64 * self.alphabet.bits_per_char()
+    }
+
+    /// Checks that the brand is usable and keys keep at least [`MIN_SECRET_BITS`] of secret
+    pub fn validate(&self) -> Result<(), KeyGenerationError> {
+        if self.brand.is_empty() || !self.brand.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(KeyGenerationError::UnsafeFormat(format!(
+                "brand must be non-empty and alphanumeric, got {:?}",
+                self.brand
+            )));
+        }
+        if self.brand.len() > MAX_BRAND_LEN {
+            return Err(KeyGenerationError::UnsafeFormat(format!(
+                "brand must be at most {} characters, got {}",
+                MAX_BRAND_LEN,
+                self.brand.len()
+            )));
+        }
+
+        let bits = self.secret_bits();
+        if bits < MIN_SECRET_BITS {
+            return Err(KeyGenerationError::UnsafeFormat(format!(
+                "keys would carry {:.0} secret bits, at least {} are required",
+                bits, MIN_SECRET_BITS
+            )));
+        }
+        Ok(())
+    }
+
+    /// Generates a key of `kind` in `env` granted `scopes`, using the OS random source and clock
+    pub fn generate(
+        &self,
+        kind: KeyKind,
+        env: Environment,
+        scopes: Vec<Scope>,
+    ) -> Result<(ParsedApiKey, ApiKeyMetadata), KeyGenerationError> {
+        KeyGenerator::default()
+            .with_format(self.clone())
+            .generate(kind, env, scopes)
+    }
+
+    /// Returns the kind and environment named by a key's prefix
+    pub fn parse_prefix(&self, key: &str) -> Option<(KeyKind, Environment)> {
+        let environments = Environment::all();
+        [KeyKind::Secret, KeyKind::Publishable]
+            .into_iter()
+            .flat_map(|kind| environments.iter().map(move |env| (kind, *env)))
+            .find(|(kind, env)| key.starts_with(&self.prefix(*kind, *env)))
+    }
+
+    /// Validates that `key` is in this format, optionally in `expected_env`
+    pub fn validate_key(&self, key: &str, expected_env: Option<Environment>) -> Result<(), KeyGenerationError> {
+        let parsed = self.parse(key)?;
+
+        // Check environment match if provided
+        match expected_env {
+            Some(expected) if parsed.environment() != expected => Err(KeyGenerationError::InvalidFormat),
+            _ => Ok(()),
+        }
+    }
+
+    /// Parses a key in this format into its segments
+    ///
+    /// Fails with [`KeyGenerationError::UnsafeFormat`] if the format itself
+    /// doesn't pass [`KeyFormat::validate`].
+    pub fn parse(&self, key: &str) -> Result<ParsedApiKey, KeyGenerationError> {
+        // A format too short to hold a secret can't be split into segments
+        self.validate()?;
+
+        // Check if key starts with valid prefix
+        let (kind, environment) = self.parse_prefix(key).ok_or(KeyGenerationError::InvalidFormat)?;
+
+        // Check total length
+        if key.len() != self.key_len(environment) {
+            return Err(KeyGenerationError::InvalidFormat);
+        }
+
+        // Check that the key only uses the format's alphabet after the prefix
+        let prefix_len = self.prefix(kind, environment).len();
+        let checksum_len = self.alphabet.checksum_len();
+
+        if !key[prefix_len..].chars().all(|c| self.alphabet.contains(c)) {
             return Err(KeyGenerationError::InvalidFormat);
         }
+
+        // Catch mistyped or truncated keys without a storage round trip
+        let (body, expected) = key.split_at(key.len() - checksum_len);
+        if self.alphabet.checksum(body) != expected {
+            return Err(KeyGenerationError::InvalidChecksum);
+        }
+
+        Ok(ParsedApiKey {
+            key: SecretApiKey::new(key.to_string()),
+            kind,
+            environment,
+            layout: Layout::Formatted { prefix_len, checksum_len },
+        })
+    }
+
+    fn random_len(&self, kind: KeyKind, env: Environment) -> Option<usize> {
+        // Total length - prefix - timestamp - checksum
+        self.key_len(env)
+            .checked_sub(self.prefix(kind, env).len() + 8 + self.alphabet.checksum_len())
+    }
+}
+
+/// A key whose format and checksum have been checked, split into its segments
+///
+/// ```text
+/// tronch_sk_live_ 87654321 WXdP2Pjq 80vVO8WD0W3eqUV 2CZkVc
+/// `-- prefix --'  `-- lookup id --' `-- secret --' checksum
+///                 timestamp
+/// ```
+///
+/// Storage, rotation and validation all take keys in this form, so a key is
+/// parsed once, where it enters the system. Keys issued before the tronch
+/// format can be wrapped with [`ParsedApiKey::legacy`], given the same
+/// [`LegacyKeys`] they were imported with.
+///
+/// `Debug` and `Display` show the key redacted, like [`SecretApiKey`].
+///
+/// # Examples
+/// ```
+/// use tronch::generation::{generate_api_key, Environment, KeyKind, ParsedApiKey};
+///
+/// let (key, metadata) = generate_api_key(Environment::Live).unwrap();
+/// let parsed: ParsedApiKey = key.expose_secret().parse().unwrap();
+/// assert_eq!(parsed.kind(), KeyKind::Secret);
+/// assert_eq!(parsed.environment(), Environment::Live);
+/// assert_eq!(parsed.lookup_id(), metadata.lookup_id);
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct ParsedApiKey {
+    key: SecretApiKey,
+    kind: KeyKind,
+    environment: Environment,
+    layout: Layout,
+}
+
+/// Where a parsed key's segments are
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Layout {
+    /// A key in some [`KeyFormat`], whose lookup id follows its prefix
+    Formatted { prefix_len: usize, checksum_len: usize },
+    /// A key with no segments at all, indexed on a digest of the whole key
+    Legacy { lookup_id: String },
+}
+
+impl ParsedApiKey {
+    /// Parses a key in the default tronch format
+    pub fn parse(key: &str) -> Result<Self, KeyGenerationError> {
+        KeyFormat::default().parse(key)
+    }
+
+    /// Wraps a key issued before the tronch format, such as those in the legacy store.json
+    ///
+    /// Such keys have no prefix to name their environment, nor a lookup id;
+    /// `legacy` supplies the environment and derives the id. Any string is
+    /// accepted, since there is no format to check it against.
+    pub fn legacy(key: &str, legacy: &LegacyKeys) -> Self {
+        Self {
+            key: SecretApiKey::new(key.to_string()),
+            kind: KeyKind::Secret,
+            environment: legacy.environment,
+            layout: Layout::Legacy { lookup_id: legacy.lookup_id(&sha256_hex(key)) },
+        }
+    }
+
+    pub fn kind(&self) -> KeyKind {
+        self.kind
+    }
+
+    pub fn environment(&self) -> Environment {
+        self.environment
+    }
+
+    /// Whether the key predates the tronch format, and so has no prefix, timestamp or checksum
+    pub fn is_legacy(&self) -> bool {
+        matches!(self.layout, Layout::Legacy { .. })
+    }
+
+    /// The full plaintext key
+    pub fn expose_secret(&self) -> &str {
+        self.key.expose_secret()
+    }
+
+    pub fn into_secret(self) -> SecretApiKey {
+        self.key
+    }
+
+    fn segment(&self, range: std::ops::Range<usize>) -> &str {
+        &self.expose_secret()[range]
+    }
+
+    /// The brand, kind and environment segment, e.g. `tronch_sk_live_`
+    pub fn prefix(&self) -> &str {
+        match self.layout {
+            Layout::Formatted { prefix_len, .. } => self.segment(0..prefix_len),
+            Layout::Legacy { .. } => "",
+        }
     }
 
-    // Check total length (should be 52 chars)
-    if key.len() != 52 {
-        return Err(KeyGenerationError::InvalidFormat);
+    /// The 8 reversed digits of the creation time
+    pub fn timestamp(&self) -> &str {
+        match self.layout {
+            Layout::Formatted { prefix_len, .. } => self.segment(prefix_len..prefix_len + 8),
+            Layout::Legacy { .. } => "",
+        }
     }
 
-    // Check that the key contains only alphanumeric characters after the prefix
-    let prefix_len = if key.starts_with("tronch_sk_test_") {
-        "tronch_sk_test_".len()
-    } else {
-        "tronch_sk_live_".len()
-    };
+    /// The non-secret identifier storage indexes the key on
+    pub fn lookup_id(&self) -> &str {
+        match &self.layout {
+            Layout::Formatted { prefix_len, .. } => self.segment(*prefix_len..prefix_len + LOOKUP_ID_LEN),
+            Layout::Legacy { lookup_id } => lookup_id,
+        }
+    }
 
-    if !key[prefix_len..].chars().all(|c| c.is_ascii_alphanumeric()) {
-        return Err(KeyGenerationError::InvalidFormat);
+    /// The random characters that follow the lookup id, which only the key holder knows
+    pub fn secret(&self) -> &str {
+        let len = self.expose_secret().len();
+        match self.layout {
+            Layout::Formatted { prefix_len, checksum_len } => {
+                self.segment(prefix_len + LOOKUP_ID_LEN..len - checksum_len)
+            }
+            Layout::Legacy { .. } => self.expose_secret(),
+        }
     }
 
-    Ok(())
+    pub fn checksum(&self) -> &str {
+        let len = self.expose_secret().len();
+        match self.layout {
+            Layout::Formatted { checksum_len, .. } => self.segment(len - checksum_len..len),
+            Layout::Legacy { .. } => "",
+        }
+    }
+}
+
+/// How keys issued before the tronch format are recognized
+///
+/// Legacy keys carry no lookup id, so one is derived from the whole key: an
+/// HMAC-SHA256, under a server-side pepper, of the SHA-256 digest the legacy
+/// store kept. Without the pepper, ids read from a leaked store can't be used
+/// to test guesses offline.
+///
+/// # Examples
+/// ```
+/// use tronch::generation::{Environment, LegacyKeys, ParsedApiKey};
+///
+/// let legacy = LegacyKeys::new(Environment::Live, [7u8; 32]).unwrap();
+/// let key = ParsedApiKey::legacy("an old key", &legacy);
+/// assert!(key.is_legacy());
+/// assert_eq!(key.lookup_id().len(), 64);
+/// ```
+#[derive(Clone)]
+pub struct LegacyKeys {
+    environment: Environment,
+    pepper: Zeroizing<Vec<u8>>,
+}
+
+impl LegacyKeys {
+    /// Treats legacy keys as belonging to `environment`, keying their ids with `pepper`
+    ///
+    /// The pepper must be at least [`MIN_PEPPER_LEN`] bytes, and must stay the
+    /// same for as long as imported keys are in use.
+    pub fn new(environment: Environment, pepper: impl Into<Vec<u8>>) -> Result<Self, HashingError> {
+        let pepper = Zeroizing::new(pepper.into());
+        if pepper.len() < MIN_PEPPER_LEN {
+            return Err(HashingError::InvalidPepper(format!(
+                "legacy key pepper must be at least {} bytes",
+                MIN_PEPPER_LEN
+            )));
+        }
+        Ok(Self { environment, pepper })
+    }
+
+    pub fn environment(&self) -> Environment {
+        self.environment
+    }
+
+    /// The lookup id of the key whose hex SHA-256 digest is `digest`
+    pub(crate) fn lookup_id(&self, digest: &str) -> String {
+        hmac_sha256_hex(&self.pepper, &digest.to_ascii_lowercase())
+    }
+}
+
+impl fmt::Debug for LegacyKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LegacyKeys")
+            .field("environment", &self.environment)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for ParsedApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.key, f)
+    }
+}
+
+impl fmt::Display for ParsedApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.key, f)
+    }
+}
+
+impl FromStr for ParsedApiKey {
+    type Err = KeyGenerationError;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        ParsedApiKey::parse(key)
+    }
+}
+
+/// Generates keys from an injectable random source and clock
+///
+/// The free functions in this module use the thread-local CSPRNG and the
+/// system clock; seed a generator instead to get reproducible keys in tests.
+///
+/// # Examples
+/// ```
+/// use std::sync::Arc;
+/// use rand::{rngs::StdRng, SeedableRng};
+/// use tronch::generation::{Environment, KeyGenerator, KeyKind};
+/// use tronch::rate_limit::FixedTimeProvider;
+///
+/// let generate = || {
+///     let mut generator = KeyGenerator::new(StdRng::seed_from_u64(7), Arc::new(FixedTimeProvider(1_700_000_000)));
+///     generator.generate(KeyKind::Secret, Environment::Test, vec![]).unwrap().0
+/// };
+/// assert_eq!(generate(), generate());
+/// ```
+#[derive(Debug)]
+pub struct KeyGenerator<R = ThreadRng> {
+    rng: R,
+    time_provider: Arc<dyn TimeProvider>,
+    format: KeyFormat,
+    hasher: Arc<dyn KeyHasher>,
+}
+
+impl Default for KeyGenerator<ThreadRng> {
+    fn default() -> Self {
+        Self::new(rand::thread_rng(), Arc::new(SystemTimeProvider))
+    }
+}
+
+impl<R: RngCore + CryptoRng> KeyGenerator<R> {
+    /// Generates default-format keys from `rng`, timestamped by `time_provider`
+    pub fn new(rng: R, time_provider: Arc<dyn TimeProvider>) -> Self {
+        Self {
+            rng,
+            time_provider,
+            format: KeyFormat::default(),
+            hasher: Arc::new(Argon2Hasher::default()),
+        }
+    }
+
+    pub fn with_format(mut self, format: KeyFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn format(&self) -> &KeyFormat {
+        &self.format
+    }
+
+    /// Hashes generated keys with `hasher` instead of the default Argon2 parameters
+    pub fn with_hasher(mut self, hasher: Arc<dyn KeyHasher>) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Generates a key of `kind` in `env` granted `scopes`
+    pub fn generate(
+        &mut self,
+        kind: KeyKind,
+        env: Environment,
+        scopes: Vec<Scope>,
+    ) -> Result<(ParsedApiKey, ApiKeyMetadata), KeyGenerationError> {
+        let format = &self.format;
+        format.validate()?;
+
+        // Generate a timestamp component (8 chars)
+        let now = self.time_provider.current_time();
+        if now < 0 {
+            return Err(KeyGenerationError::GenerationFailed);
+        }
+        let timestamp = now
+            .to_string()
+            .chars()
+            .rev()
+            .take(8)
+            .collect::<String>();
+
+        // Calculate remaining length for random component
+        let prefix = format.prefix(kind, env);
+        let random_len = format.random_len(kind, env).ok_or(KeyGenerationError::GenerationFailed)?;
+
+        // Generate a random component
+        let chars = format.alphabet.chars();
+        let random: Zeroizing<String> = Zeroizing::new(
+            (0..random_len)
+                .map(|_| char::from(chars[self.rng.gen_range(0..chars.len())]))
+                .collect(),
+        );
+
+        let body = Zeroizing::new(format!("{}{}{}", prefix, timestamp, *random));
+        let key = Zeroizing::new(format!("{}{}", *body, format.alphabet.checksum(&body)));
+
+        // Validate the generated key
+        let parsed = format.parse(&key)?;
+
+        // Create metadata with hash
+        let mut metadata = ApiKeyMetadata::with_hasher(&parsed, &*self.hasher)?;
+        metadata.created_at = DateTime::from_timestamp(now, 0).ok_or(KeyGenerationError::GenerationFailed)?;
+        metadata.scopes = scopes;
+
+        Ok((parsed, metadata))
+    }
+
+    /// Generates a secret key together with a publishable key, each recording the other's lookup id
+    pub fn generate_key_pair(&mut self, env: Environment) -> Result<KeyPair, KeyGenerationError> {
+        let (secret_key, mut secret_metadata) = self.generate(KeyKind::Secret, env, Scope::all())?;
+        let (publishable_key, mut publishable_metadata) =
+            self.generate(KeyKind::Publishable, env, KeyKind::Publishable.default_scopes())?;
+
+        secret_metadata.paired_key = Some(publishable_metadata.lookup_id.clone());
+        publishable_metadata.paired_key = Some(secret_metadata.lookup_id.clone());
+
+        Ok(KeyPair {
+            secret_key,
+            secret_metadata,
+            publishable_key,
+            publishable_metadata,
+        })
+    }
 }
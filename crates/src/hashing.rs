@@ -1,8 +1,42 @@
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
-    Argon2, PasswordVerifier,
+    password_hash::{rand_core::OsRng, Output, PasswordHash, PasswordHasher, SaltString},
+    Algorithm, Argon2, KeyId, Params, ParamsBuilder, PasswordVerifier, Version,
 };
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
 use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// PHC identifier of SHA-256 digests imported from the legacy store.json
+const LEGACY_SHA256: &str = "sha256";
+
+/// PHC identifier of peppered HMAC-SHA256 hashes
+const HMAC_SHA256: &str = "hmac-sha256";
+
+/// PHC parameter recording which pepper version an HMAC-SHA256 hash was made with
+const PEPPER_PARAM: &str = "pepper";
+
+/// Shortest pepper [`PepperSet`] accepts, in bytes
+pub const MIN_PEPPER_LEN: usize = 32;
+
+/// Memory cost calibration starts from, in KiB
+const CALIBRATION_MIN_MEMORY: u32 = 8 * 1024;
+
+/// Memory cost calibration stops at, in KiB
+const CALIBRATION_MAX_MEMORY: u32 = 1024 * 1024;
+
+/// Number of passes calibration stops at
+const CALIBRATION_MAX_TIME_COST: u32 = 10;
+
+/// Stand-in key for benchmarks and dummy verifies, the length of a default-format key
+const SAMPLE_KEY: &str = "tronch_sk_test_87654321WXdP2Pjq80vVO8WD0W3eqUV2CZkVc";
 
 #[derive(Error, Debug)]
 pub enum HashingError {
@@ -10,62 +44,659 @@ pub enum HashingError {
     HashError(String),
     #[error("Failed to verify key: {0}")]
     VerifyError(String),
+    #[error("Invalid pepper: {0}")]
+    InvalidPepper(String),
+    #[error("Calibration failed: {0}")]
+    CalibrationError(String),
+}
+
+/// Argon2 variant and cost parameters used to hash new keys
+///
+/// Defaults to Argon2id with the `argon2` crate's recommended costs, which
+/// is what every key was hashed with before this was configurable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashingConfig {
+    /// Argon2 variant
+    pub algorithm: Algorithm,
+    /// Memory cost in KiB
+    pub memory_cost: u32,
+    /// Number of passes over memory
+    pub time_cost: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl Default for HashingConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::Argon2id,
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
 }
 
-/// Represents a hashed API key with its salt
+impl HashingConfig {
+    /// Builds the Argon2 context, recording the pepper's version as the PHC `keyid`
+    fn argon2<'k>(&self, pepper: Option<(u32, &'k [u8])>) -> Result<Argon2<'k>, HashingError> {
+        let mut builder = ParamsBuilder::new();
+        builder
+            .m_cost(self.memory_cost)
+            .t_cost(self.time_cost)
+            .p_cost(self.parallelism);
+        if let Some((version, _)) = pepper {
+            let keyid = KeyId::new(&version.to_be_bytes())
+                .map_err(|e| HashingError::HashError(e.to_string()))?;
+            builder.keyid(keyid);
+        }
+        let params = builder
+            .build()
+            .map_err(|e| HashingError::HashError(e.to_string()))?;
+
+        match pepper {
+            Some((_, secret)) => Argon2::new_with_secret(secret, self.algorithm, Version::V0x13, params)
+                .map_err(|e| HashingError::HashError(e.to_string())),
+            None => Ok(Argon2::new(self.algorithm, Version::V0x13, params)),
+        }
+    }
+
+    /// Times `samples` calls to [`KeyHash::verify`] on a hash made with this config
+    pub fn benchmark(&self, samples: usize) -> Result<HashingBenchmark, HashingError> {
+        if samples == 0 {
+            return Err(HashingError::CalibrationError("at least one sample is needed".to_string()));
+        }
+
+        let hash = KeyHash::with_config(SAMPLE_KEY, self)?;
+        let mut times = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            let start = Instant::now();
+            hash.verify(SAMPLE_KEY)?;
+            times.push(start.elapsed());
+        }
+
+        Ok(HashingBenchmark::from_samples(*self, times))
+    }
+
+    /// Recommends the costliest parameters whose median verify stays within `target`
+    ///
+    /// Memory cost is doubled first, since that is what makes guessing
+    /// expensive on GPUs, and then passes are added. The variant and
+    /// parallelism are kept from `self`.
+    ///
+    /// # Arguments
+    /// * `target` - Acceptable median latency of one verify
+    /// * `samples` - Verifies timed for each candidate
+    pub fn calibrate(&self, target: Duration, samples: usize) -> Result<HashingConfig, HashingError> {
+        self.calibrate_with(target, |config| Ok(config.benchmark(samples)?.p50))
+    }
+
+    fn calibrate_with(
+        &self,
+        target: Duration,
+        mut measure: impl FnMut(&HashingConfig) -> Result<Duration, HashingError>,
+    ) -> Result<HashingConfig, HashingError> {
+        let mut best = HashingConfig {
+            memory_cost: CALIBRATION_MIN_MEMORY,
+            time_cost: 1,
+            ..*self
+        };
+        let floor = measure(&best)?;
+        if floor > target {
+            return Err(HashingError::CalibrationError(format!(
+                "verifying with m={}, t=1 already takes {:?}",
+                CALIBRATION_MIN_MEMORY, floor
+            )));
+        }
+
+        while best.memory_cost < CALIBRATION_MAX_MEMORY {
+            let next = HashingConfig {
+                memory_cost: best.memory_cost * 2,
+                ..best
+            };
+            if measure(&next)? > target {
+                break;
+            }
+            best = next;
+        }
+
+        while best.time_cost < CALIBRATION_MAX_TIME_COST {
+            let next = HashingConfig {
+                time_cost: best.time_cost + 1,
+                ..best
+            };
+            if measure(&next)? > target {
+                break;
+            }
+            best = next;
+        }
+
+        Ok(best)
+    }
+}
+
+/// Verify latencies measured by [`HashingConfig::benchmark`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HashingBenchmark {
+    /// Parameters that were measured
+    pub config: HashingConfig,
+    /// Median verify time
+    pub p50: Duration,
+    /// 99th percentile verify time
+    pub p99: Duration,
+    /// Verifies per second on one thread
+    pub throughput: f64,
+}
+
+impl HashingBenchmark {
+    fn from_samples(config: HashingConfig, mut times: Vec<Duration>) -> Self {
+        times.sort();
+        let total: Duration = times.iter().sum();
+        // Nearest-rank percentile
+        let percentile = |p: usize| times[(times.len() * p).div_ceil(100).max(1) - 1];
+
+        Self {
+            config,
+            p50: percentile(50),
+            p99: percentile(99),
+            throughput: times.len() as f64 / total.as_secs_f64().max(f64::MIN_POSITIVE),
+        }
+    }
+}
+
+/// Server-held secrets mixed into key hashes, by version
+///
+/// Each hash records the version of the pepper it was made with. New hashes
+/// use the highest version; older ones are only kept so that hashes made
+/// before a rotation keep verifying until their keys are rehashed.
+///
+/// # Examples
+/// ```
+/// use tronch::hashing::PepperSet;
+///
+/// let peppers = PepperSet::new(1, [1u8; 32])
+///     .unwrap()
+///     .with_version(2, [2u8; 32])
+///     .unwrap();
+/// assert_eq!(peppers.current_version(), 2);
+/// assert!(peppers.get(1).is_some());
+/// ```
+#[derive(Clone)]
+pub struct PepperSet {
+    peppers: BTreeMap<u32, Zeroizing<Vec<u8>>>,
+}
+
+impl PepperSet {
+    /// Starts a set whose only pepper is `pepper`, at least [`MIN_PEPPER_LEN`] bytes long
+    pub fn new(version: u32, pepper: impl Into<Vec<u8>>) -> Result<Self, HashingError> {
+        Self { peppers: BTreeMap::new() }.with_version(version, pepper)
+    }
+
+    /// Adds another version; whichever version is highest hashes new keys
+    pub fn with_version(mut self, version: u32, pepper: impl Into<Vec<u8>>) -> Result<Self, HashingError> {
+        let pepper = Zeroizing::new(pepper.into());
+        if pepper.len() < MIN_PEPPER_LEN {
+            return Err(HashingError::InvalidPepper(format!(
+                "version {} must be at least {} bytes",
+                version, MIN_PEPPER_LEN
+            )));
+        }
+        if self.peppers.contains_key(&version) {
+            return Err(HashingError::InvalidPepper(format!("version {} is given twice", version)));
+        }
+
+        self.peppers.insert(version, pepper);
+        Ok(self)
+    }
+
+    /// Reads peppers from a file with one `<version>:<hex pepper>` per line
+    ///
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, HashingError> {
+        let contents = Zeroizing::new(
+            fs::read_to_string(path).map_err(|e| HashingError::InvalidPepper(e.to_string()))?,
+        );
+
+        let mut peppers = Self { peppers: BTreeMap::new() };
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (version, pepper) = line
+                .split_once(':')
+                .ok_or_else(|| HashingError::InvalidPepper("expected <version>:<hex pepper>".to_string()))?;
+            let version = version
+                .trim()
+                .parse()
+                .map_err(|_| HashingError::InvalidPepper(format!("invalid version {:?}", version)))?;
+            let pepper = decode_hex(pepper.trim())
+                .ok_or_else(|| HashingError::InvalidPepper(format!("version {} is not valid hex", version)))?;
+            peppers = peppers.with_version(version, pepper)?;
+        }
+
+        if peppers.peppers.is_empty() {
+            return Err(HashingError::InvalidPepper("no peppers in file".to_string()));
+        }
+        Ok(peppers)
+    }
+
+    /// Version new hashes are made with
+    pub fn current_version(&self) -> u32 {
+        self.current().0
+    }
+
+    /// Returns the pepper for `version`, if it is still kept
+    pub fn get(&self, version: u32) -> Option<&[u8]> {
+        self.peppers.get(&version).map(|pepper| pepper.as_slice())
+    }
+
+    fn current(&self) -> (u32, &[u8]) {
+        let (version, pepper) = self
+            .peppers
+            .iter()
+            .next_back()
+            .expect("a pepper set is never empty");
+        (*version, pepper.as_slice())
+    }
+}
+
+impl fmt::Debug for PepperSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PepperSet")
+            .field("versions", &self.peppers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Scheme a stored [`KeyHash`] was made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// Salted Argon2, with its parameters in the PHC string
+    Argon2,
+    /// HMAC-SHA256 keyed with a server-side pepper
+    HmacSha256,
+    /// Unsalted SHA-256 digest from the legacy store.json
+    LegacySha256,
+}
+
+/// Hashes API keys for storage and verifies keys against stored hashes
+///
+/// Storages and [`KeyGenerator`](crate::generation::KeyGenerator) take an
+/// `Arc<dyn KeyHasher>`, so a deployment can trade Argon2's resistance to
+/// offline guessing for the speed of [`HmacSha256Hasher`]. Every hasher
+/// verifies Argon2 and legacy hashes too, so switching doesn't lock out
+/// existing keys; they are rehashed as they are looked up.
+pub trait KeyHasher: fmt::Debug + Send + Sync {
+    /// Hashes a key for storage
+    fn hash(&self, key: &str) -> Result<KeyHash, HashingError>;
+
+    /// Verifies a key against a stored hash
+    fn verify(&self, hash: &KeyHash, key: &str) -> Result<bool, HashingError>;
+
+    /// Whether `hash` differs from what [`KeyHasher::hash`] would produce now
+    fn needs_rehash(&self, hash: &KeyHash) -> Result<bool, HashingError>;
+
+    /// Spends about as long as [`KeyHasher::verify`] on a key that has no stored hash
+    ///
+    /// The default hashes a throwaway key every time, doubling the cost;
+    /// implementations should keep one around instead.
+    fn verify_dummy(&self, key: &str) -> Result<(), HashingError> {
+        self.verify(&self.hash(SAMPLE_KEY)?, key).map(|_| ())
+    }
+}
+
+/// Hashes keys with salted Argon2, the default
+///
+/// With [`Argon2Hasher::with_peppers`] the current pepper is passed to Argon2
+/// as its secret input, so a database dump alone isn't enough to test
+/// guesses against the hashes.
+#[derive(Debug, Clone, Default)]
+pub struct Argon2Hasher {
+    config: HashingConfig,
+    peppers: Option<PepperSet>,
+    /// Made on the first miss, with the same costs as real hashes
+    dummy: OnceLock<KeyHash>,
+}
+
+impl Argon2Hasher {
+    pub fn new(config: HashingConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Mixes the current pepper into new hashes, and accepts every version in `peppers`
+    pub fn with_peppers(mut self, peppers: PepperSet) -> Self {
+        self.peppers = Some(peppers);
+        self.dummy = OnceLock::new();
+        self
+    }
+
+    pub fn config(&self) -> &HashingConfig {
+        &self.config
+    }
+}
+
+impl KeyHasher for Argon2Hasher {
+    fn hash(&self, key: &str) -> Result<KeyHash, HashingError> {
+        KeyHash::argon2(key, &self.config, self.peppers.as_ref().map(PepperSet::current))
+    }
+
+    fn verify(&self, hash: &KeyHash, key: &str) -> Result<bool, HashingError> {
+        hash.verify_peppered(key, self.peppers.as_ref())
+    }
+
+    fn needs_rehash(&self, hash: &KeyHash) -> Result<bool, HashingError> {
+        hash.needs_argon2_rehash(&self.config, self.peppers.as_ref().map(PepperSet::current_version))
+    }
+
+    fn verify_dummy(&self, key: &str) -> Result<(), HashingError> {
+        let dummy = match self.dummy.get() {
+            Some(dummy) => dummy,
+            None => {
+                let dummy = self.hash(SAMPLE_KEY)?;
+                self.dummy.get_or_init(|| dummy)
+            }
+        };
+        self.verify(dummy, key).map(|_| ())
+    }
+}
+
+/// Hashes keys with HMAC-SHA256 keyed by a server-side pepper
+///
+/// Generated keys carry at least 80 random bits, so a single keyed hash is
+/// already infeasible to reverse and verifies in microseconds rather than
+/// the tens of milliseconds Argon2 takes. The pepper must live outside the
+/// key store: a leaked table is useless without it, but every hash made
+/// with it stops verifying if it is lost or changed.
+#[derive(Debug, Clone)]
+pub struct HmacSha256Hasher {
+    peppers: PepperSet,
+}
+
+impl HmacSha256Hasher {
+    /// Uses `pepper` as version 1, which must be at least [`MIN_PEPPER_LEN`] bytes
+    pub fn new(pepper: impl Into<Vec<u8>>) -> Result<Self, HashingError> {
+        Ok(Self::from_peppers(PepperSet::new(1, pepper)?))
+    }
+
+    /// Hashes with the current pepper in `peppers`, and accepts every version in it
+    pub fn from_peppers(peppers: PepperSet) -> Self {
+        Self { peppers }
+    }
+}
+
+impl KeyHasher for HmacSha256Hasher {
+    fn hash(&self, key: &str) -> Result<KeyHash, HashingError> {
+        let (version, pepper) = self.peppers.current();
+        KeyHash::hmac_sha256(version, &hmac_sha256(pepper, key)?)
+    }
+
+    fn verify(&self, hash: &KeyHash, key: &str) -> Result<bool, HashingError> {
+        hash.verify_peppered(key, Some(&self.peppers))
+    }
+
+    fn needs_rehash(&self, hash: &KeyHash) -> Result<bool, HashingError> {
+        Ok(hash.algorithm() != HashAlgorithm::HmacSha256
+            || hash.pepper_version()? != Some(self.peppers.current_version()))
+    }
+
+    fn verify_dummy(&self, key: &str) -> Result<(), HashingError> {
+        // Computing the digest is all a verify costs
+        self.hash(key).map(|_| ())
+    }
+}
+
+/// Represents a hashed API key as a self-describing PHC string
+///
+/// Argon2 hashes use the standard `$argon2id$v=19$m=..,t=..,p=..$salt$hash`
+/// form. The other schemes follow the same shape with their own identifier:
+/// `$hmac-sha256$pepper=<version>$<digest>` and `$sha256$<digest>`, with
+/// digests in unpadded B64 as in the PHC spec.
 #[derive(Debug, Clone)]
 pub struct KeyHash {
-    hash: String,
-    salt: String,
+    phc: String,
 }
 
 impl KeyHash {
     /// Creates a new KeyHash from an API key
     pub fn new(key: &str) -> Result<Self, HashingError> {
+        Self::with_config(key, &HashingConfig::default())
+    }
+
+    /// Creates a new KeyHash from an API key using the given Argon2 parameters
+    pub fn with_config(key: &str, config: &HashingConfig) -> Result<Self, HashingError> {
+        Self::argon2(key, config, None)
+    }
+
+    fn argon2(key: &str, config: &HashingConfig, pepper: Option<(u32, &[u8])>) -> Result<Self, HashingError> {
         let salt = SaltString::generate(&mut OsRng);
-        let argon2 = Argon2::default();
-        
+        let argon2 = config.argon2(pepper)?;
+
         let hash = argon2
             .hash_password(key.as_bytes(), &salt)
             .map_err(|e| HashingError::HashError(e.to_string()))?;
 
+        Ok(Self { phc: hash.to_string() })
+    }
+
+    fn hmac_sha256(pepper_version: u32, digest: &[u8]) -> Result<Self, HashingError> {
         Ok(Self {
-            hash: hash.to_string(),
-            salt: salt.to_string(),
+            phc: format!("${}${}={}${}", HMAC_SHA256, PEPPER_PARAM, pepper_version, b64(digest)?),
         })
     }
 
+    /// Wraps an unsalted SHA-256 digest from the legacy store.json
+    pub fn from_legacy_digest(digest: &str) -> Result<Self, HashingError> {
+        let digest = decode_hex(digest)
+            .filter(|digest| digest.len() == 32)
+            .ok_or_else(|| HashingError::HashError("Invalid legacy digest".to_string()))?;
+
+        Ok(Self {
+            phc: format!("${}${}", LEGACY_SHA256, b64(&digest)?),
+        })
+    }
+
+    /// Scheme this hash was made with
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self.fields().first() {
+            Some(&LEGACY_SHA256) => HashAlgorithm::LegacySha256,
+            Some(&HMAC_SHA256) => HashAlgorithm::HmacSha256,
+            _ => HashAlgorithm::Argon2,
+        }
+    }
+
+    /// Version of the pepper this hash was made with, if any
+    pub fn pepper_version(&self) -> Result<Option<u32>, HashingError> {
+        let invalid = || HashingError::VerifyError("Invalid pepper version".to_string());
+        match self.algorithm() {
+            HashAlgorithm::LegacySha256 => Ok(None),
+            HashAlgorithm::HmacSha256 => self.fields()[1]
+                .strip_prefix(PEPPER_PARAM)
+                .and_then(|param| param.strip_prefix('='))
+                .and_then(|version| version.parse().ok())
+                .map(Some)
+                .ok_or_else(invalid),
+            HashAlgorithm::Argon2 => {
+                let params = Params::try_from(&self.argon2_phc()?)
+                    .map_err(|e| HashingError::VerifyError(e.to_string()))?;
+                match params.keyid() {
+                    [] => Ok(None),
+                    keyid => Ok(Some(u32::from_be_bytes(keyid.try_into().map_err(|_| invalid())?))),
+                }
+            }
+        }
+    }
+
     /// Verifies a key against this hash
+    ///
+    /// Peppered hashes can't be checked without their pepper, so they must
+    /// be verified through the [`KeyHasher`] that holds it instead.
     pub fn verify(&self, key: &str) -> Result<bool, HashingError> {
-        let hash = PasswordHash::new(&self.hash)
-            .map_err(|e| HashingError::VerifyError(e.to_string()))?;
+        self.verify_peppered(key, None)
+    }
+
+    fn verify_peppered(&self, key: &str, peppers: Option<&PepperSet>) -> Result<bool, HashingError> {
+        let pepper = match self.pepper_version()? {
+            Some(version) => Some(peppers.and_then(|p| p.get(version)).ok_or_else(|| {
+                HashingError::VerifyError(format!("Pepper version {} is not available", version))
+            })?),
+            None => None,
+        };
+
+        match self.algorithm() {
+            HashAlgorithm::LegacySha256 => {
+                let expected = b64(&Sha256::digest(key.as_bytes()))?;
+                Ok(expected.as_bytes().ct_eq(self.digest().as_bytes()).into())
+            }
+            HashAlgorithm::HmacSha256 => {
+                let pepper = pepper.ok_or_else(|| HashingError::VerifyError("Missing pepper".to_string()))?;
+                let expected = b64(&hmac_sha256(pepper, key)?)?;
+                Ok(expected.as_bytes().ct_eq(self.digest().as_bytes()).into())
+            }
+            HashAlgorithm::Argon2 => {
+                let argon2 = match pepper {
+                    Some(secret) => Argon2::new_with_secret(secret, Algorithm::default(), Version::default(), Params::default())
+                        .map_err(|e| HashingError::VerifyError(e.to_string()))?,
+                    None => Argon2::default(),
+                };
+
+                match argon2.verify_password(key.as_bytes(), &self.argon2_phc()?) {
+                    Ok(_) => Ok(true),
+                    Err(argon2::password_hash::Error::Password) => Ok(false),
+                    Err(e) => Err(HashingError::VerifyError(e.to_string())),
+                }
+            }
+        }
+    }
+
+    /// Whether this hash was made differently from what `config` would produce now
+    ///
+    /// Legacy SHA-256 digests, HMAC-SHA256 hashes and peppered hashes always need rehashing.
+    pub fn needs_rehash(&self, config: &HashingConfig) -> Result<bool, HashingError> {
+        self.needs_argon2_rehash(config, None)
+    }
 
-        match Argon2::default().verify_password(key.as_bytes(), &hash) {
-            Ok(_) => Ok(true),
-            Err(argon2::password_hash::Error::Password) => Ok(false),
-            Err(e) => Err(HashingError::VerifyError(e.to_string())),
+    fn needs_argon2_rehash(&self, config: &HashingConfig, pepper_version: Option<u32>) -> Result<bool, HashingError> {
+        if self.algorithm() != HashAlgorithm::Argon2 || self.pepper_version()? != pepper_version {
+            return Ok(true);
         }
+
+        let hash = self.argon2_phc()?;
+        let algorithm = Algorithm::try_from(hash.algorithm)
+            .map_err(|e| HashingError::VerifyError(e.to_string()))?;
+        let params = Params::try_from(&hash)
+            .map_err(|e| HashingError::VerifyError(e.to_string()))?;
+
+        Ok(algorithm != config.algorithm
+            || hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != config.memory_cost
+            || params.t_cost() != config.time_cost
+            || params.p_cost() != config.parallelism)
+    }
+
+    /// The `$`-separated fields after the leading `$`
+    fn fields(&self) -> Vec<&str> {
+        self.phc.split('$').skip(1).collect()
     }
 
-    /// Serializes the hash for storage
-    pub fn to_string(&self) -> String {
-        format!("{}:{}", self.salt, self.hash)
+    /// The digest of an HMAC-SHA256 or legacy SHA-256 hash
+    fn digest(&self) -> &str {
+        self.phc.rsplit('$').next().unwrap_or_default()
+    }
+
+    fn argon2_phc(&self) -> Result<PasswordHash<'_>, HashingError> {
+        PasswordHash::new(&self.phc).map_err(|e| HashingError::VerifyError(e.to_string()))
     }
 
     /// Deserializes a hash from storage
+    ///
+    /// Also accepts the `salt:hash` form Argon2 hashes were stored in before
+    /// they were plain PHC strings; [`KeyHash::is_legacy_encoding`] tells such
+    /// a string apart so it can be written back in the current form.
     pub fn from_string(s: &str) -> Result<Self, HashingError> {
-        let parts: Vec<&str> = s.split(':').collect();
-        if parts.len() != 2 {
-            return Err(HashingError::VerifyError("Invalid hash format".to_string()));
+        let invalid = || HashingError::VerifyError("Invalid hash format".to_string());
+
+        if !s.starts_with('$') {
+            return Self::from_legacy_encoding(s).ok_or_else(invalid);
         }
 
-        Ok(Self {
-            salt: parts[0].to_string(),
-            hash: parts[1].to_string(),
-        })
+        let hash = Self { phc: s.to_string() };
+        let fields = hash.fields();
+        let valid = match hash.algorithm() {
+            HashAlgorithm::LegacySha256 => fields.len() == 2,
+            HashAlgorithm::HmacSha256 => fields.len() == 3 && hash.pepper_version().is_ok(),
+            HashAlgorithm::Argon2 => hash.argon2_phc().is_ok(),
+        };
+        if !valid || Output::b64_decode(hash.digest()).is_err() {
+            return Err(invalid());
+        }
+
+        Ok(hash)
+    }
+
+    /// Converts a `salt:hash` string into the PHC form
+    fn from_legacy_encoding(s: &str) -> Option<Self> {
+        // Argon2 hashes were already PHC strings, prefixed with a copy of their salt
+        let (_, hash) = s.split_once(':')?;
+        Self::from_string(hash)
+            .ok()
+            .filter(|hash| hash.algorithm() == HashAlgorithm::Argon2)
+    }
+
+    /// Whether `s` is in the `salt:hash` form from before hashes were PHC strings
+    pub fn is_legacy_encoding(s: &str) -> bool {
+        !s.starts_with('$')
+    }
+}
+
+/// Serializes the hash for storage
+impl fmt::Display for KeyHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.phc)
     }
 }
 
+/// Returns the hex-encoded SHA-256 digest of a key or request body
+pub fn sha256_hex(data: impl AsRef<[u8]>) -> String {
+    hex(&Sha256::digest(data.as_ref()))
+}
+
+/// Returns the hex-encoded HMAC-SHA256 of `data` under `pepper`
+pub(crate) fn hmac_sha256_hex(pepper: &[u8], data: &str) -> String {
+    hex(&hmac_sha256(pepper, data).expect("HMAC takes keys of any length"))
+}
+
+fn hmac_sha256(pepper: &[u8], key: &str) -> Result<[u8; 32], HashingError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(pepper)
+        .map_err(|e| HashingError::HashError(e.to_string()))?;
+    mac.update(key.as_bytes());
+    Ok(mac.finalize().into_bytes().into())
+}
+
+/// Encodes a digest as unpadded B64, as PHC strings do
+fn b64(digest: &[u8]) -> Result<String, HashingError> {
+    Output::new(digest)
+        .map(|output| output.to_string())
+        .map_err(|e| HashingError::HashError(e.to_string()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,6 +721,35 @@ mod tests {
         assert!(deserialized.verify(key).unwrap());
     }
 
+    #[test]
+    fn test_calibration_search() {
+        // Pretend a verify costs 1ms per 8 MiB per pass
+        let cost = |config: &HashingConfig| {
+            Ok(Duration::from_millis((config.memory_cost / CALIBRATION_MIN_MEMORY * config.time_cost) as u64))
+        };
+
+        let config = HashingConfig::default().calibrate_with(Duration::from_millis(20), cost).unwrap();
+        assert_eq!((config.memory_cost, config.time_cost), (16 * CALIBRATION_MIN_MEMORY, 1));
+
+        let config = HashingConfig::default().calibrate_with(Duration::from_millis(300), cost).unwrap();
+        assert_eq!((config.memory_cost, config.time_cost), (128 * CALIBRATION_MIN_MEMORY, 2));
+
+        // Caps apply even with an unreachable target
+        let config = HashingConfig::default().calibrate_with(Duration::from_secs(3600), cost).unwrap();
+        assert_eq!((config.memory_cost, config.time_cost), (CALIBRATION_MAX_MEMORY, CALIBRATION_MAX_TIME_COST));
+
+        assert!(HashingConfig::default().calibrate_with(Duration::ZERO, cost).is_err());
+    }
+
+    #[test]
+    fn test_benchmark_percentiles() {
+        let times = (1..=200).map(Duration::from_millis).collect();
+        let benchmark = HashingBenchmark::from_samples(HashingConfig::default(), times);
+        assert_eq!(benchmark.p50, Duration::from_millis(100));
+        assert_eq!(benchmark.p99, Duration::from_millis(198));
+        assert!((benchmark.throughput - 200.0 / 20.1).abs() < 1e-9);
+    }
+
     #[test]
     fn test_invalid_hash_format() {
         assert!(KeyHash::from_string("invalid").is_err());
@@ -1,34 +1,59 @@
+pub mod authentication;
+pub mod authorization;
 pub mod error;
+pub mod file_store;
 pub mod generation;
 pub mod rate_limit;
 pub mod request;
 pub mod rotation;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod storage;
+pub mod usage;
 pub mod validation;
 pub mod audit;
 pub mod hashing;
 pub mod health;
 pub mod metrics;
 pub mod logging;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod secret;
+
+pub use authentication::{authenticate, authenticate_parsed, AuthenticatedKey};
+pub use authorization::{authorize, AuthorizationError, Scope};
+pub use error::{ApiKeyError, Result};
+pub use hashing::{Argon2Hasher, HashingBenchmark, HashingConfig, HmacSha256Hasher, KeyHasher, PepperSet};
+pub use generation::{generate_api_key, generate_api_key_with_scopes, generate_key_pair, generate_publishable_key, validate_key_format, CustomEnvironment, Environment, KeyAlphabet, KeyFormat, KeyGenerationError, KeyGenerator, KeyKind, KeyPair, LegacyKeys, ParsedApiKey};
+pub use rate_limit::{RateLimitConfig, RateLimiter};
+pub use request::{RequestMetadata, RequestValidator};
+pub use rotation::{rotate_key, RotationConfig, KeyRotationError};
+pub use secret::SecretApiKey;
+pub use file_store::FileStorage;
+#[cfg(feature = "postgres")]
+pub use postgres::{PostgresConfig, PostgresStorage};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+pub use storage::{ApiKeyStorage, InMemoryStorage, StorageError};
+pub use usage::{KeyUsage, UsageTracker};
+pub use validation::{validate_api_key, validate_parsed_api_key, validate_parsed_api_key_with_hasher, validate_verified_api_key, ApiKeyMetadata, ApiKeyValidationError};
+
+pub use audit::{AuditLogger, AuditEvent, AuditEventType, AuditError};
 
 #[cfg(test)]
 mod tests {
     pub mod audit;
+    pub mod authentication;
+    pub mod authorization;
+    pub mod generation;
     pub mod hashing;
     pub mod health;
     pub mod metrics;
     pub mod rate_limit;
+    pub mod request;
     pub mod rotation;
+    pub mod secret;
+    pub mod storage;
+    pub mod usage;
     mod logging;
 }
-
-pub use error::{ApiKeyError, Result};
-pub use generation::{generate_api_key, validate_key_format, Environment, KeyGenerationError};
-pub use rate_limit::{RateLimitConfig, RateLimiter};
-pub use request::{RequestMetadata, RequestValidator};
-pub use rotation::{rotate_key, RotationConfig, KeyRotationError};
-pub use storage::{ApiKeyStorage, InMemoryStorage, StorageError};
-pub use validation::{validate_api_key, ApiKeyMetadata, ApiKeyValidationError};
-
-// Re-export important types
-pub use audit::{AuditLogger, AuditEvent, AuditEventType, AuditError};
@@ -0,0 +1,274 @@
+use std::sync::Arc;
+use std::time::Duration;
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
+use sqlx::types::Json;
+use sqlx::{Postgres, Transaction};
+use crate::error::StorageError as BackendError;
+use crate::generation::{Environment, ParsedApiKey};
+use crate::hashing::{Argon2Hasher, KeyHasher};
+use crate::storage::{verify_found, ApiKeyStorage, StorageError};
+use crate::usage::KeyUsage;
+use crate::validation::ApiKeyMetadata;
+
+/// Configuration for the Postgres connection pool
+#[derive(Debug, Clone)]
+pub struct PostgresConfig {
+    /// Maximum number of pooled connections
+    pub max_connections: u32,
+    /// How long to wait for a free connection before giving up
+    pub acquire_timeout: Duration,
+    /// Schema to create the tables in, instead of the connection's default
+    pub schema: Option<String>,
+    /// Verifies keys, upgrading other hashes to its own as their keys are looked up
+    pub hasher: Arc<dyn KeyHasher>,
+}
+
+impl Default for PostgresConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            acquire_timeout: Duration::from_secs(5),
+            schema: None,
+            hasher: Arc::new(Argon2Hasher::default()),
+        }
+    }
+}
+
+/// PostgreSQL storage implementation using the `api_keys` table from PLAN.md
+///
+/// The `metadata` JSONB column holds the full record; the other columns mirror
+/// it so keys can be queried by environment, status or age. Available with
+/// the `postgres` feature.
+#[derive(Debug, Clone)]
+pub struct PostgresStorage {
+    pool: PgPool,
+    hasher: Arc<dyn KeyHasher>,
+}
+
+impl PostgresStorage {
+    /// Connects to the database at `url` and runs pending migrations
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn open() -> Result<(), tronch::StorageError> {
+    /// use tronch::postgres::{PostgresConfig, PostgresStorage};
+    ///
+    /// let storage = PostgresStorage::connect(
+    ///     "postgres://tronch@localhost/tronch",
+    ///     PostgresConfig::default(),
+    /// ).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(url: &str, config: PostgresConfig) -> Result<Self, StorageError> {
+        let mut options: PgConnectOptions = url.parse()?;
+        if let Some(schema) = &config.schema {
+            options = options.options([("search_path", schema.as_str())]);
+        }
+
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .connect_with(options)
+            .await?;
+
+        if let Some(schema) = &config.schema {
+            sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS \"{}\"", schema.replace('"', "\"\"")))
+                .execute(&pool)
+                .await
+                .map_err(|e| BackendError::MigrationError(e.to_string()))?;
+        }
+
+        sqlx::migrate!("./migrations/postgres")
+            .run(&pool)
+            .await
+            .map_err(|e| BackendError::MigrationError(e.to_string()))?;
+
+        Ok(Self {
+            pool,
+            hasher: config.hasher,
+        })
+    }
+
+    async fn begin(&self) -> Result<Transaction<'_, Postgres>, StorageError> {
+        self.pool
+            .begin()
+            .await
+            .map_err(|e| BackendError::TransactionError(e.to_string()).into())
+    }
+
+    /// Finds and verifies a key, locking its row for the rest of the transaction
+    async fn find_for_update(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        key: &ParsedApiKey,
+    ) -> Result<Option<String>, StorageError> {
+        let lookup_id = key.lookup_id().to_string();
+        let row: Option<(Json<ApiKeyMetadata>,)> =
+            sqlx::query_as("SELECT metadata FROM api_keys WHERE lookup_id = $1 FOR UPDATE")
+                .bind(&lookup_id)
+                .fetch_optional(&mut **tx)
+                .await?;
+
+        let found = row.map(|(Json(metadata),)| metadata);
+        Ok(verify_found(found, key, &*self.hasher)?.map(|_| lookup_id))
+    }
+}
+
+async fn commit(tx: Transaction<'_, Postgres>) -> Result<(), StorageError> {
+    tx.commit()
+        .await
+        .map_err(|e| BackendError::TransactionError(e.to_string()).into())
+}
+
+fn status(metadata: &ApiKeyMetadata) -> &'static str {
+    if metadata.is_revoked {
+        "revoked"
+    } else if !metadata.is_active {
+        "inactive"
+    } else {
+        "active"
+    }
+}
+
+#[async_trait::async_trait]
+impl ApiKeyStorage for PostgresStorage {
+    async fn store_key(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
+        // The unique lookup_id constraint rejects duplicates, surfacing as KeyExists
+        sqlx::query(
+            "INSERT INTO api_keys \
+             (lookup_id, key_prefix, key_hash, environment, status, created_at, last_used_at, expires_at, metadata) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(key.lookup_id())
+        .bind(key.prefix())
+        .bind(&metadata.key_hash)
+        .bind(metadata.environment.as_str())
+        .bind(status(&metadata))
+        .bind(metadata.created_at)
+        .bind(metadata.last_used_at)
+        .bind(metadata.expires_at)
+        .bind(Json(&metadata))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_metadata(&self, key: &ParsedApiKey) -> Result<ApiKeyMetadata, StorageError> {
+        let lookup_id = key.lookup_id().to_string();
+        let row: Option<(Json<ApiKeyMetadata>,)> =
+            sqlx::query_as("SELECT metadata FROM api_keys WHERE lookup_id = $1")
+                .bind(&lookup_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        let found = row.map(|(Json(metadata),)| metadata);
+        let mut metadata = verify_found(found, key, &*self.hasher)?.ok_or(StorageError::KeyNotFound)?;
+
+        let old_hash = metadata.key_hash.clone();
+        if metadata.rehash_if_needed(key.expose_secret(), &*self.hasher)? {
+            // Leave the row alone if it was changed since we read it
+            sqlx::query(
+                "UPDATE api_keys SET key_hash = $1, metadata = jsonb_set(metadata, '{key_hash}', to_jsonb($1::text)) \
+                 WHERE lookup_id = $2 AND key_hash = $3",
+            )
+            .bind(&metadata.key_hash)
+            .bind(lookup_id)
+            .bind(old_hash)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(metadata)
+    }
+
+    async fn update_metadata(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
+        let mut tx = self.begin().await?;
+        let lookup_id = match self.find_for_update(&mut tx, key).await? {
+            Some(id) => id,
+            None => return Err(StorageError::KeyNotFound),
+        };
+
+        // Usage is left to record_usage, so the stored usage fields win over the new document's
+        sqlx::query(
+            "UPDATE api_keys SET \
+             key_prefix = $1, key_hash = $2, environment = $3, status = $4, \
+             created_at = $5, expires_at = $6, metadata = $7 || jsonb_build_object( \
+                 'last_used_at', metadata->'last_used_at', \
+                 'last_used_ip', metadata->'last_used_ip', \
+                 'request_count', COALESCE(metadata->'request_count', '0'::jsonb)) \
+             WHERE lookup_id = $8",
+        )
+        .bind(key.prefix())
+        .bind(&metadata.key_hash)
+        .bind(metadata.environment.as_str())
+        .bind(status(&metadata))
+        .bind(metadata.created_at)
+        .bind(metadata.expires_at)
+        .bind(Json(&metadata))
+        .bind(lookup_id)
+        .execute(&mut *tx)
+        .await?;
+
+        commit(tx).await
+    }
+
+    async fn delete_key(&self, key: &ParsedApiKey) -> Result<(), StorageError> {
+        let mut tx = self.begin().await?;
+        let lookup_id = match self.find_for_update(&mut tx, key).await? {
+            Some(id) => id,
+            None => return Err(StorageError::KeyNotFound),
+        };
+
+        sqlx::query("DELETE FROM api_keys WHERE lookup_id = $1")
+            .bind(lookup_id)
+            .execute(&mut *tx)
+            .await?;
+
+        commit(tx).await
+    }
+
+    async fn list_keys(&self, environment: Environment) -> Result<Vec<String>, StorageError> {
+        let ids = sqlx::query_scalar("SELECT lookup_id FROM api_keys WHERE environment = $1")
+            .bind(environment.as_str())
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(ids)
+    }
+
+    async fn record_usage(&self, usage: &[KeyUsage]) -> Result<(), StorageError> {
+        // Count and address live only in the metadata document
+        let mut tx = self.begin().await?;
+        for usage in usage {
+            sqlx::query(
+                "UPDATE api_keys SET last_used_at = $1, metadata = metadata || jsonb_build_object( \
+                     'last_used_at', $2::jsonb, \
+                     'last_used_ip', COALESCE($3::jsonb, metadata->'last_used_ip'), \
+                     'request_count', COALESCE((metadata->>'request_count')::bigint, 0) + $4) \
+                 WHERE lookup_id = $5",
+            )
+            .bind(usage.last_used_at)
+            .bind(Json(usage.last_used_at))
+            .bind(usage.last_used_ip.map(Json))
+            .bind(usage.requests as i64)
+            .bind(&usage.lookup_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        commit(tx).await
+    }
+
+    async fn set_paired_key(&self, lookup_id: &str, paired_key: Option<&str>) -> Result<(), StorageError> {
+        let result = sqlx::query(
+            "UPDATE api_keys SET metadata = jsonb_set(metadata, '{paired_key}', $1::jsonb) \
+             WHERE lookup_id = $2",
+        )
+        .bind(Json(paired_key))
+        .bind(lookup_id)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(StorageError::KeyNotFound);
+        }
+        Ok(())
+    }
+}
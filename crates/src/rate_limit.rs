@@ -3,6 +3,7 @@ use std::sync::Arc;
 use chrono::{Duration, Utc};
 use dashmap::DashMap;
 use thiserror::Error;
+use crate::generation::ParsedApiKey;
 use crate::storage::ApiKeyStorage;
 use async_trait::async_trait;
 
@@ -20,6 +21,16 @@ impl TimeProvider for SystemTimeProvider {
     }
 }
 
+/// A clock stopped at the given Unix timestamp, for reproducible tests
+#[derive(Debug, Clone, Copy)]
+pub struct FixedTimeProvider(pub i64);
+
+impl TimeProvider for FixedTimeProvider {
+    fn current_time(&self) -> i64 {
+        self.0
+    }
+}
+
 /// Configuration for rate limiting
 #[derive(Debug, Clone)]
 pub struct RateLimitConfig {
@@ -134,7 +145,8 @@ impl InMemoryRateLimitStorage {
 #[async_trait]
 impl RateLimitStorage for InMemoryRateLimitStorage {
     async fn get_metadata(&self, key: &str) -> Result<(), RateLimitError> {
-        self.api_storage.get_metadata(key).await.map_err(|_| RateLimitError::InvalidKey)?;
+        let key = ParsedApiKey::parse(key).map_err(|_| RateLimitError::InvalidKey)?;
+        self.api_storage.get_metadata(&key).await.map_err(|_| RateLimitError::InvalidKey)?;
         Ok(())
     }
 
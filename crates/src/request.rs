@@ -1,7 +1,19 @@
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use chrono::{DateTime, Utc};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
 use thiserror::Error;
+use crate::hashing::sha256_hex;
+use crate::validation::ApiKeyMetadata;
+
+type HmacSha256 = Hmac<Sha256>;
 
 #[derive(Debug, Error, Clone, Serialize, Deserialize, PartialEq)]
 pub enum RequestValidationError {
@@ -11,10 +23,233 @@ pub enum RequestValidationError {
     IpNotAllowed(IpAddr),
     #[error("Request timestamp too old: {0}")]
     RequestTooOld(DateTime<Utc>),
+    #[error("Request timestamp in the future: {0}")]
+    RequestFromFuture(DateTime<Utc>),
     #[error("Missing required header: {0}")]
     MissingHeader(String),
     #[error("Invalid header value: {0}")]
     InvalidHeaderValue(String),
+    #[error("Request replayed: {0}")]
+    ReplayedRequest(String),
+}
+
+/// Remembers recently seen request ids so replayed requests can be rejected
+///
+/// Ids are recorded per key: `scope` is the lookup id of the key the request
+/// was made with, or empty for requests not tied to a key, so one key's ids
+/// never collide with another's.
+pub trait ReplayStore: Send + Sync + std::fmt::Debug {
+    /// Records `request_id` under `scope` until `expires_at`, returning false if it is already recorded
+    fn check_and_insert(&self, scope: &str, request_id: &str, expires_at: DateTime<Utc>) -> bool;
+}
+
+/// How many inserts happen between sweeps of expired request ids
+const REPLAY_PURGE_INTERVAL: usize = 1024;
+
+/// In-memory replay store, used by default
+#[derive(Debug, Default)]
+pub struct InMemoryReplayStore {
+    seen: DashMap<(String, String), DateTime<Utc>>,
+    inserts: AtomicUsize,
+}
+
+impl InMemoryReplayStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets request ids whose replay window has passed
+    pub fn purge_expired(&self) {
+        let now = Utc::now();
+        self.seen.retain(|_, expires_at| *expires_at > now);
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+impl ReplayStore for InMemoryReplayStore {
+    fn check_and_insert(&self, scope: &str, request_id: &str, expires_at: DateTime<Utc>) -> bool {
+        let fresh = match self.seen.entry((scope.to_string(), request_id.to_string())) {
+            Entry::Occupied(mut entry) => {
+                if *entry.get() > Utc::now() {
+                    return false;
+                }
+                entry.insert(expires_at);
+                true
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(expires_at);
+                true
+            }
+        };
+
+        if self.inserts.fetch_add(1, Ordering::Relaxed) % REPLAY_PURGE_INTERVAL == REPLAY_PURGE_INTERVAL - 1 {
+            self.purge_expired();
+        }
+        fresh
+    }
+}
+
+/// Most `version=signature` entries a signature header may hold
+pub const MAX_SIGNATURE_ENTRIES: usize = 8;
+
+/// Request signing schemes understood by [`RequestValidator::validate_signature`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureVersion {
+    /// HMAC-SHA256 keyed with the API key over the canonical request string
+    V1,
+}
+
+impl SignatureVersion {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            SignatureVersion::V1 => "v1",
+        }
+    }
+
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "v1" => Some(SignatureVersion::V1),
+            _ => None,
+        }
+    }
+
+    /// Returns the hex-encoded signature of a request
+    pub fn sign(&self, request: &SignedRequest<'_>, api_key: &str) -> String {
+        match self {
+            SignatureVersion::V1 => {
+                let mut mac = HmacSha256::new_from_slice(api_key.as_bytes())
+                    .expect("HMAC accepts keys of any length");
+                mac.update(request.canonical().as_bytes());
+                mac.finalize()
+                    .into_bytes()
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// The parts of a request its signature covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub body: &'a [u8],
+    /// The timestamp header exactly as sent, since that is what the client signed
+    pub timestamp: &'a str,
+    pub request_id: &'a str,
+}
+
+impl SignedRequest<'_> {
+    /// Builds the string a signature covers: method, path, timestamp, request id and body digest
+    pub fn canonical(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            self.method.to_ascii_uppercase(),
+            self.path,
+            self.timestamp,
+            self.request_id,
+            sha256_hex(self.body)
+        )
+    }
+}
+
+/// Signs a request, returning the value for the signature header
+///
+/// # Examples
+/// ```
+/// use tronch::request::{sign_request, RequestValidator, SignedRequest};
+///
+/// let key = "tronch_sk_test_secret";
+/// let request = SignedRequest {
+///     method: "POST",
+///     path: "/api/v1/keys",
+///     body: b"{}",
+///     timestamp: "2024-01-01T00:00:00Z",
+///     request_id: "req-1",
+/// };
+/// let signature = sign_request(&request, key);
+///
+/// let validator = RequestValidator::new(chrono::Duration::minutes(5), None);
+/// assert!(validator.validate_signature(&request, &signature, key).is_ok());
+/// ```
+pub fn sign_request(request: &SignedRequest<'_>, api_key: &str) -> String {
+    let version = SignatureVersion::V1;
+    format!("{}={}", version.tag(), version.sign(request, api_key))
+}
+
+/// Networks a request may come from, for a whole validator or a single key
+///
+/// Deny rules take precedence over allow rules. IPv4-mapped IPv6 addresses
+/// are matched as the IPv4 address they carry.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IpPolicy {
+    /// Networks requests are allowed from; `None` allows any address
+    #[serde(default)]
+    pub allow: Option<Vec<IpNet>>,
+    /// Networks requests are always rejected from
+    #[serde(default)]
+    pub deny: Vec<IpNet>,
+}
+
+impl IpPolicy {
+    /// Allows only the given networks
+    pub fn allow(networks: Vec<IpNet>) -> Self {
+        Self {
+            allow: Some(networks),
+            deny: Vec::new(),
+        }
+    }
+
+    /// Adds networks to reject even when they fall inside an allowed range
+    pub fn with_deny(mut self, networks: Vec<IpNet>) -> Self {
+        self.deny.extend(networks);
+        self
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+
+        match &self.allow {
+            Some(allow) => allow.iter().any(|net| net.contains(&ip)),
+            None => true,
+        }
+    }
+}
+
+/// The header trusted proxies put the client address in
+///
+/// Only the configured header is read; clients can send any of the others
+/// straight through a proxy that doesn't strip them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ForwardedHeader {
+    /// RFC 7239 `Forwarded`, e.g. `for=192.0.2.60;proto=http`
+    Forwarded,
+    /// `X-Forwarded-For`, a comma-separated list of addresses
+    XForwardedFor,
+    /// `X-Real-IP`, the single address the proxy saw
+    XRealIp,
+}
+
+impl ForwardedHeader {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForwardedHeader::Forwarded => "Forwarded",
+            ForwardedHeader::XForwardedFor => "X-Forwarded-For",
+            ForwardedHeader::XRealIp => "X-Real-IP",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,51 +260,230 @@ pub struct RequestMetadata {
     pub request_id: String,
 }
 
+/// Default tolerance for client clocks running ahead of or behind ours
+const DEFAULT_CLOCK_SKEW_SECS: i64 = 30;
+
 #[derive(Debug, Clone)]
 pub struct RequestValidator {
     max_request_age: chrono::Duration,
-    allowed_ips: Option<Vec<IpAddr>>,
+    max_clock_skew: chrono::Duration,
+    ip_policy: IpPolicy,
+    replay_store: Arc<dyn ReplayStore>,
+    trusted_proxies: Vec<IpNet>,
+    forwarded_header: ForwardedHeader,
 }
 
 impl RequestValidator {
     pub fn new(max_request_age: chrono::Duration, allowed_ips: Option<Vec<IpAddr>>) -> Self {
+        let ip_policy = IpPolicy {
+            allow: allowed_ips.map(|ips| ips.into_iter().map(IpNet::from).collect()),
+            deny: Vec::new(),
+        };
+
         Self {
             max_request_age,
-            allowed_ips,
+            max_clock_skew: chrono::Duration::seconds(DEFAULT_CLOCK_SKEW_SECS),
+            ip_policy,
+            replay_store: Arc::new(InMemoryReplayStore::new()),
+            trusted_proxies: Vec::new(),
+            forwarded_header: ForwardedHeader::XForwardedFor,
         }
     }
 
+    /// Trusts `header` when set by proxies in these networks
+    pub fn with_trusted_proxies(mut self, proxies: Vec<IpNet>, header: ForwardedHeader) -> Self {
+        self.trusted_proxies = proxies;
+        self.forwarded_header = header;
+        self
+    }
+
+    /// Sets how far client clocks may drift from ours, in either direction
+    pub fn with_clock_skew(mut self, max_clock_skew: chrono::Duration) -> Self {
+        self.max_clock_skew = max_clock_skew;
+        self
+    }
+
+    /// Replaces the exact-address allowlist with a network policy
+    pub fn with_ip_policy(mut self, policy: IpPolicy) -> Self {
+        self.ip_policy = policy;
+        self
+    }
+
+    /// Uses `store` to remember request ids instead of the in-memory default
+    pub fn with_replay_store(mut self, store: Arc<dyn ReplayStore>) -> Self {
+        self.replay_store = store;
+        self
+    }
+
     pub fn validate_request(&self, metadata: &RequestMetadata) -> Result<(), RequestValidationError> {
-        // Validate request age
-        let age = Utc::now() - metadata.timestamp;
-        if age > self.max_request_age {
+        self.check_request(metadata, None, None)
+    }
+
+    /// Validates a request made with a specific key, also enforcing the key's own network policy
+    pub fn validate_request_for_key(
+        &self,
+        metadata: &RequestMetadata,
+        key: &ApiKeyMetadata,
+    ) -> Result<(), RequestValidationError> {
+        self.check_request(metadata, Some(key), None)
+    }
+
+    /// Validates a signed request made with a specific key
+    ///
+    /// Does everything [`validate_request_for_key`](Self::validate_request_for_key)
+    /// does, but only records the request id once the signature has been
+    /// verified, so a forged request can't use up the id of a genuine one.
+    pub fn validate_signed_request(
+        &self,
+        metadata: &RequestMetadata,
+        key: &ApiKeyMetadata,
+        request: &SignedRequest<'_>,
+        signature: &str,
+        api_key: &str,
+    ) -> Result<(), RequestValidationError> {
+        self.check_request(metadata, Some(key), Some((request, signature, api_key)))
+    }
+
+    fn check_request(
+        &self,
+        metadata: &RequestMetadata,
+        key: Option<&ApiKeyMetadata>,
+        signed: Option<(&SignedRequest<'_>, &str, &str)>,
+    ) -> Result<(), RequestValidationError> {
+        // Validate request age, allowing for clock skew both ways
+        let now = Utc::now();
+        if metadata.timestamp > now + self.max_clock_skew {
+            return Err(RequestValidationError::RequestFromFuture(metadata.timestamp));
+        }
+        if now - metadata.timestamp > self.max_request_age + self.max_clock_skew {
             return Err(RequestValidationError::RequestTooOld(metadata.timestamp));
         }
 
-        // Validate IP if allowed_ips is set
-        if let Some(allowed_ips) = &self.allowed_ips {
-            if !allowed_ips.contains(&metadata.ip_address) {
+        // Validate IP against the validator's policy, then the key's
+        let policies = std::iter::once(&self.ip_policy).chain(key.map(|key| &key.ip_policy));
+        for policy in policies {
+            if !policy.is_allowed(metadata.ip_address) {
                 return Err(RequestValidationError::IpNotAllowed(metadata.ip_address));
             }
         }
 
+        // The signature must cover the id being recorded, and be checked before it is
+        if let Some((request, signature, api_key)) = signed {
+            if request.request_id != metadata.request_id {
+                return Err(RequestValidationError::InvalidSignature);
+            }
+            self.validate_signature(request, signature, api_key)?;
+        }
+
+        // Reject replays; a request id only needs remembering while its timestamp is accepted
+        let scope = key.map_or("", |key| key.lookup_id.as_str());
+        let expires_at = metadata.timestamp + self.max_request_age + self.max_clock_skew;
+        if !self.replay_store.check_and_insert(scope, &metadata.request_id, expires_at) {
+            return Err(RequestValidationError::ReplayedRequest(metadata.request_id.clone()));
+        }
+
         Ok(())
     }
 
+    /// Verifies a signature header against the request it claims to sign
+    ///
+    /// The header holds comma-separated `version=signature` pairs, e.g.
+    /// `v1=5257a869...`, so clients can send several signatures while a new
+    /// scheme rolls out. Unknown versions are ignored; the request is accepted
+    /// if any supported signature matches. Headers with more than
+    /// [`MAX_SIGNATURE_ENTRIES`] entries are rejected outright.
+    ///
+    /// This doesn't check for replays; use
+    /// [`validate_signed_request`](Self::validate_signed_request) for that.
     pub fn validate_signature(
         &self,
-        _request_body: &[u8],
-        _signature: &str,
-        _timestamp: &str,
-        _api_key: &str,
+        request: &SignedRequest<'_>,
+        signature: &str,
+        api_key: &str,
     ) -> Result<(), RequestValidationError> {
-        // TODO: Implement HMAC signature validation
-        // This is a placeholder for the actual signature validation logic
-        // In production, you would:
-        // 1. Concatenate request_body + timestamp
-        // 2. Generate HMAC using api_key as secret
-        // 3. Compare with provided signature
-        Ok(())
+        if signature.split(',').count() > MAX_SIGNATURE_ENTRIES {
+            return Err(RequestValidationError::InvalidHeaderValue(format!(
+                "signature has more than {} entries",
+                MAX_SIGNATURE_ENTRIES
+            )));
+        }
+
+        // Each version's signature covers the whole body, so work it out once
+        let mut expected: Vec<(SignatureVersion, String)> = Vec::new();
+        let mut matched = false;
+        for entry in signature.split(',') {
+            let (tag, value) = match entry.trim().split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let version = match SignatureVersion::from_tag(tag) {
+                Some(version) => version,
+                None => continue,
+            };
+
+            let signed = match expected.iter().find(|(signed_version, _)| *signed_version == version) {
+                Some((_, signed)) => signed,
+                None => {
+                    expected.push((version, version.sign(request, api_key)));
+                    &expected[expected.len() - 1].1
+                }
+            };
+            // Check every candidate so timing doesn't reveal which one matched
+            matched |= bool::from(signed.as_bytes().ct_eq(value.to_ascii_lowercase().as_bytes()));
+        }
+
+        if matched {
+            Ok(())
+        } else {
+            Err(RequestValidationError::InvalidSignature)
+        }
+    }
+
+    /// Extracts request metadata, resolving the client IP through trusted proxies
+    pub fn extract_client_metadata(
+        &self,
+        headers: &[(String, String)],
+        peer_ip: IpAddr,
+    ) -> Result<RequestMetadata, RequestValidationError> {
+        let ip = self.client_ip(headers, peer_ip)?;
+        Self::extract_metadata(headers, ip)
+    }
+
+    /// Resolves the address of the client behind any trusted proxies
+    ///
+    /// The configured forwarding header is only read when the connection comes
+    /// from a trusted proxy. Its chain is walked right to left, skipping
+    /// trusted hops; the first untrusted address is the client, since anything
+    /// further left could have been supplied by the client itself. A hop that
+    /// hides its address (RFC 7239 `unknown` or an obfuscated `_name`) ends
+    /// the walk, leaving the last proxy that saw it as the client.
+    pub fn client_ip(
+        &self,
+        headers: &[(String, String)],
+        peer_ip: IpAddr,
+    ) -> Result<IpAddr, RequestValidationError> {
+        if !self.is_trusted_proxy(peer_ip) {
+            return Ok(peer_ip);
+        }
+
+        // Entries left of the client are never parsed, so junk a client adds can't fail the request
+        let header = self.forwarded_header;
+        let mut client = peer_ip;
+        for node in forwarded_chain(headers, header).iter().rev() {
+            client = match parse_node(node, header)? {
+                Some(ip) => ip,
+                None => break,
+            };
+            if !self.is_trusted_proxy(client) {
+                break;
+            }
+        }
+        Ok(client)
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
     }
 
     pub fn extract_metadata(
@@ -105,4 +519,66 @@ impl RequestValidator {
             request_id,
         })
     }
-} 
\ No newline at end of file
+}
+
+/// Joins every value of a header, in order, as one comma-separated list
+fn header_values(headers: &[(String, String)], name: &str) -> Option<String> {
+    let values: Vec<&str> = headers
+        .iter()
+        .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+        .collect();
+
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(","))
+    }
+}
+
+/// Returns the raw forwarded-for chain from `header`, leftmost (original client) first
+fn forwarded_chain(headers: &[(String, String)], header: ForwardedHeader) -> Vec<String> {
+    let values = match header_values(headers, header.name()) {
+        Some(values) => values,
+        None => return Vec::new(),
+    };
+
+    match header {
+        // RFC 7239: `for=192.0.2.60;proto=http, for="[2001:db8::17]:4711"`
+        ForwardedHeader::Forwarded => values
+            .split(',')
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    name.trim().eq_ignore_ascii_case("for").then(|| value.trim().to_string())
+                })
+            })
+            .collect(),
+        ForwardedHeader::XForwardedFor => values.split(',').map(|node| node.trim().to_string()).collect(),
+        ForwardedHeader::XRealIp => vec![values.trim().to_string()],
+    }
+}
+
+/// Parses a forwarded node: an IP address, optionally quoted, bracketed or with a port
+///
+/// Returns `None` for RFC 7239 `unknown` and obfuscated (`_name`) nodes,
+/// which stand in for an address the proxy chose not to reveal.
+fn parse_node(node: &str, header: ForwardedHeader) -> Result<Option<IpAddr>, RequestValidationError> {
+    let node = node.trim_matches('"');
+    let address = if let Some(rest) = node.strip_prefix('[') {
+        rest.split(']').next().unwrap_or_default()
+    } else if node.matches(':').count() == 1 {
+        // IPv4 with a port; bare IPv6 addresses have several colons
+        node.split(':').next().unwrap_or_default()
+    } else {
+        node
+    };
+
+    if address.eq_ignore_ascii_case("unknown") || address.starts_with('_') {
+        return Ok(None);
+    }
+
+    address.parse().map(Some).map_err(|_| {
+        RequestValidationError::InvalidHeaderValue(format!("Invalid {} address: {}", header.name(), node))
+    })
+}
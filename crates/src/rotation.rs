@@ -1,8 +1,8 @@
 use thiserror::Error;
 use chrono::{Duration, Utc};
 use crate::{
-    generation::generate_api_key,
-    storage::ApiKeyStorage,
+    generation::{KeyFormat, ParsedApiKey},
+    storage::{ApiKeyStorage, StorageError},
 };
 
 #[derive(Error, Debug)]
@@ -28,6 +28,8 @@ pub struct RotationConfig {
     pub grace_period: Duration,
     /// Whether to automatically revoke old keys after grace period
     pub auto_revoke: bool,
+    /// Format the replacement key is generated in
+    pub format: KeyFormat,
 }
 
 impl Default for RotationConfig {
@@ -35,6 +37,7 @@ impl Default for RotationConfig {
         Self {
             grace_period: Duration::days(7),
             auto_revoke: true,
+            format: KeyFormat::default(),
         }
     }
 }
@@ -47,12 +50,12 @@ impl Default for RotationConfig {
 /// * `config` - Rotation configuration
 /// 
 /// # Returns
-/// * `Result<String, KeyRotationError>` - The new key or an error
+/// * `Result<ParsedApiKey, KeyRotationError>` - The new key or an error
 pub async fn rotate_key(
     storage: &impl ApiKeyStorage,
-    old_key: &str,
+    old_key: &ParsedApiKey,
     config: RotationConfig,
-) -> Result<String, KeyRotationError> {
+) -> Result<ParsedApiKey, KeyRotationError> {
     // Get metadata for old key
     let metadata = storage
         .get_metadata(old_key)
@@ -64,9 +67,14 @@ pub async fn rotate_key(
         return Err(KeyRotationError::KeyRevoked);
     }
 
-    // Generate new key in same environment
-    let (new_key, new_metadata) = generate_api_key(metadata.environment)
-        .map_err(|_| KeyRotationError::GenerationFailed)?;
+    // Generate new key of the same kind in same environment, with the same permissions
+    let (new_key, mut new_metadata) =
+        config.format.generate(metadata.kind, metadata.environment, metadata.scopes.clone())
+            .map_err(|_| KeyRotationError::GenerationFailed)?;
+
+    // Carry the key's network policy and pairing over to its replacement
+    new_metadata.ip_policy = metadata.ip_policy.clone();
+    new_metadata.paired_key = metadata.paired_key.clone();
 
     // Store new key
     storage
@@ -74,6 +82,14 @@ pub async fn rotate_key(
         .await
         .map_err(|_| KeyRotationError::StorageFailed)?;
 
+    // Point the other key of the pair at the replacement, unless it has since been deleted
+    if let Some(partner) = &metadata.paired_key {
+        match storage.set_paired_key(partner, Some(new_key.lookup_id())).await {
+            Ok(()) | Err(StorageError::KeyNotFound) => {}
+            Err(_) => return Err(KeyRotationError::StorageFailed),
+        }
+    }
+
     // Update old key metadata with grace period
     let mut old_metadata = metadata;
     old_metadata.expires_at = Some(Utc::now() + config.grace_period);
@@ -0,0 +1,83 @@
+use std::fmt;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+/// Number of trailing characters left visible when a key is printed
+const VISIBLE_SUFFIX_LEN: usize = 4;
+
+/// A plaintext API key that is wiped from memory when dropped
+///
+/// `Debug` and `Display` only show the key's prefix and last few characters,
+/// e.g. `tronch_sk_live_****abcd`, so a key that ends up in a log line or a
+/// panic message can be recognised without being leaked. Reading the key
+/// takes an explicit call to [`SecretApiKey::expose_secret`].
+///
+/// # Examples
+/// ```
+/// use tronch::secret::SecretApiKey;
+///
+/// let key = SecretApiKey::new("tronch_sk_live_123456789012345678901234567890abcd".to_string());
+/// assert_eq!(key.to_string(), "tronch_sk_live_****abcd");
+/// assert_eq!(format!("{:?}", key), "tronch_sk_live_****abcd");
+/// assert!(key.expose_secret().starts_with("tronch_sk_live_1234"));
+/// ```
+#[derive(Clone)]
+pub struct SecretApiKey {
+    key: String,
+}
+
+impl SecretApiKey {
+    pub fn new(key: String) -> Self {
+        Self { key }
+    }
+
+    /// Returns the plaintext key
+    pub fn expose_secret(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the key with everything but its prefix and last characters masked
+    pub fn redacted(&self) -> String {
+        let prefix_len = self.key.rfind('_').map_or(0, |i| i + 1);
+        let rest = &self.key[prefix_len..];
+
+        // Short keys would be given away almost entirely by their suffix
+        let suffix = match rest.char_indices().rev().nth(VISIBLE_SUFFIX_LEN - 1) {
+            Some((i, _)) if rest.len() > 4 * VISIBLE_SUFFIX_LEN => &rest[i..],
+            _ => "",
+        };
+        format!("{}****{}", &self.key[..prefix_len], suffix)
+    }
+}
+
+impl Drop for SecretApiKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl From<String> for SecretApiKey {
+    fn from(key: String) -> Self {
+        Self::new(key)
+    }
+}
+
+impl PartialEq for SecretApiKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.key.as_bytes().ct_eq(other.key.as_bytes()).into()
+    }
+}
+
+impl Eq for SecretApiKey {}
+
+impl fmt::Debug for SecretApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.redacted())
+    }
+}
+
+impl fmt::Display for SecretApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.redacted())
+    }
+}
@@ -0,0 +1,285 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use sqlx::pool::PoolConnection;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteExecutor, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::Sqlite;
+use sqlx::types::Json;
+use sqlx::Row;
+use crate::error::StorageError as BackendError;
+use crate::generation::{Environment, KeyKind, ParsedApiKey};
+use crate::hashing::{Argon2Hasher, KeyHasher};
+use crate::storage::{verify_found, ApiKeyStorage, StorageError};
+use crate::usage::KeyUsage;
+use crate::validation::ApiKeyMetadata;
+
+/// SQLite storage implementation, backed by a database file or `:memory:`
+///
+/// Available with the `sqlite` feature.
+#[derive(Debug, Clone)]
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    hasher: Arc<dyn KeyHasher>,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `url`, creating it if needed, and runs pending migrations
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn open() -> Result<(), tronch::StorageError> {
+    /// use tronch::sqlite::SqliteStorage;
+    ///
+    /// let storage = SqliteStorage::connect("sqlite://keys.db").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(url: &str) -> Result<Self, StorageError> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+
+        // An in-memory database disappears with its last connection, so keep exactly one alive
+        let pool = if url.contains(":memory:") {
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect_with(options)
+                .await
+        } else {
+            SqlitePoolOptions::new().connect_with(options).await
+        }?;
+
+        Self::migrate(&pool).await?;
+        Ok(Self {
+            pool,
+            hasher: Arc::new(Argon2Hasher::default()),
+        })
+    }
+
+    /// Verifies keys with `hasher`, upgrading other hashes to it as their keys are looked up
+    pub fn with_hasher(mut self, hasher: Arc<dyn KeyHasher>) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Opens a private in-memory database
+    pub async fn in_memory() -> Result<Self, StorageError> {
+        Self::connect("sqlite::memory:").await
+    }
+
+    async fn migrate(pool: &SqlitePool) -> Result<(), StorageError> {
+        sqlx::migrate!("./migrations/sqlite")
+            .run(pool)
+            .await
+            .map_err(|e| BackendError::MigrationError(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn find<'e>(
+        &self,
+        executor: impl SqliteExecutor<'e>,
+        key: &ParsedApiKey,
+    ) -> Result<Option<(String, ApiKeyMetadata)>, StorageError> {
+        let lookup_id = key.lookup_id().to_string();
+        let row = sqlx::query("SELECT * FROM api_keys WHERE lookup_id = ?")
+            .bind(&lookup_id)
+            .fetch_optional(executor)
+            .await?;
+
+        let found = row.as_ref().map(row_to_metadata).transpose()?;
+        Ok(verify_found(found, key, &*self.hasher)?.map(|metadata| (lookup_id, metadata)))
+    }
+
+    /// Starts a transaction that holds the database's write lock from its first read
+    ///
+    /// Verifying a key and then writing to its row happen under this lock, so
+    /// a concurrent rotation or delete can't change the row in between.
+    async fn begin_immediate(&self) -> Result<PoolConnection<Sqlite>, StorageError> {
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await?;
+        Ok(conn)
+    }
+}
+
+/// Commits the transaction `begin_immediate` started if `result` is a success, rolling it back otherwise
+async fn finish<T>(mut conn: PoolConnection<Sqlite>, result: Result<T, StorageError>) -> Result<T, StorageError> {
+    let result = match result {
+        Ok(value) => sqlx::query("COMMIT").execute(&mut *conn).await.map(|_| value).map_err(Into::into),
+        Err(e) => Err(e),
+    };
+    if result.is_err() && sqlx::query("ROLLBACK").execute(&mut *conn).await.is_err() {
+        // Never hand a connection stuck in a transaction back to the pool
+        let _ = conn.close().await;
+    }
+    result
+}
+
+fn row_to_metadata(row: &SqliteRow) -> Result<ApiKeyMetadata, StorageError> {
+    let environment: String = row.try_get("environment")?;
+    let environment = Environment::try_from(environment.as_str())
+        .map_err(|_| BackendError::QueryError(format!("Unknown environment: {}", environment)))?;
+    let kind: String = row.try_get("kind")?;
+    let kind = KeyKind::try_from(kind.as_str())
+        .map_err(|_| BackendError::QueryError(format!("Unknown key kind: {}", kind)))?;
+    let last_used_ip: Option<String> = row.try_get("last_used_ip")?;
+    let last_used_ip = last_used_ip
+        .map(|ip| ip.parse().map_err(|_| BackendError::QueryError(format!("Invalid IP address: {}", ip))))
+        .transpose()?;
+
+    Ok(ApiKeyMetadata {
+        created_at: row.try_get("created_at")?,
+        last_used_at: row.try_get("last_used_at")?,
+        expires_at: row.try_get("expires_at")?,
+        environment,
+        lookup_id: row.try_get("lookup_id")?,
+        is_active: row.try_get("is_active")?,
+        is_revoked: row.try_get("is_revoked")?,
+        key_hash: row.try_get("key_hash")?,
+        ip_policy: row.try_get::<Json<_>, _>("ip_policy")?.0,
+        scopes: row.try_get::<Json<_>, _>("scopes")?.0,
+        kind,
+        paired_key: row.try_get("paired_key")?,
+        request_count: row.try_get::<i64, _>("request_count")? as u64,
+        last_used_ip,
+    })
+}
+
+#[async_trait::async_trait]
+impl ApiKeyStorage for SqliteStorage {
+    async fn store_key(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
+        // The primary key rejects duplicates, surfacing as KeyExists
+        sqlx::query(
+            "INSERT INTO api_keys \
+             (lookup_id, key_hash, environment, is_active, is_revoked, created_at, last_used_at, expires_at, ip_policy, scopes, kind, paired_key, \
+              request_count, last_used_ip) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(key.lookup_id())
+        .bind(&metadata.key_hash)
+        .bind(metadata.environment.as_str())
+        .bind(metadata.is_active)
+        .bind(metadata.is_revoked)
+        .bind(metadata.created_at)
+        .bind(metadata.last_used_at)
+        .bind(metadata.expires_at)
+        .bind(Json(&metadata.ip_policy))
+        .bind(Json(&metadata.scopes))
+        .bind(metadata.kind.as_str())
+        .bind(&metadata.paired_key)
+        .bind(metadata.request_count as i64)
+        .bind(metadata.last_used_ip.map(|ip| ip.to_string()))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_metadata(&self, key: &ParsedApiKey) -> Result<ApiKeyMetadata, StorageError> {
+        let (lookup_id, mut metadata) = match self.find(&self.pool, key).await? {
+            Some(found) => found,
+            None => return Err(StorageError::KeyNotFound),
+        };
+
+        let old_hash = metadata.key_hash.clone();
+        if metadata.rehash_if_needed(key.expose_secret(), &*self.hasher)? {
+            // Leave the row alone if it was changed since we read it
+            sqlx::query("UPDATE api_keys SET key_hash = ? WHERE lookup_id = ? AND key_hash = ?")
+                .bind(&metadata.key_hash)
+                .bind(lookup_id)
+                .bind(old_hash)
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(metadata)
+    }
+
+    async fn update_metadata(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
+        let mut conn = self.begin_immediate().await?;
+        let result = async {
+            let lookup_id = match self.find(&mut *conn, key).await? {
+                Some((id, _)) => id,
+                None => return Err(StorageError::KeyNotFound),
+            };
+
+            // Usage columns are left to record_usage
+            sqlx::query(
+                "UPDATE api_keys SET \
+                 key_hash = ?, environment = ?, is_active = ?, is_revoked = ?, \
+                 created_at = ?, expires_at = ?, ip_policy = ?, scopes = ?, \
+                 kind = ?, paired_key = ? \
+                 WHERE lookup_id = ?",
+            )
+            .bind(&metadata.key_hash)
+            .bind(metadata.environment.as_str())
+            .bind(metadata.is_active)
+            .bind(metadata.is_revoked)
+            .bind(metadata.created_at)
+            .bind(metadata.expires_at)
+            .bind(Json(&metadata.ip_policy))
+            .bind(Json(&metadata.scopes))
+            .bind(metadata.kind.as_str())
+            .bind(&metadata.paired_key)
+            .bind(lookup_id)
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        }
+        .await;
+        finish(conn, result).await
+    }
+
+    async fn delete_key(&self, key: &ParsedApiKey) -> Result<(), StorageError> {
+        let mut conn = self.begin_immediate().await?;
+        let result = async {
+            let lookup_id = match self.find(&mut *conn, key).await? {
+                Some((id, _)) => id,
+                None => return Err(StorageError::KeyNotFound),
+            };
+
+            sqlx::query("DELETE FROM api_keys WHERE lookup_id = ?")
+                .bind(lookup_id)
+                .execute(&mut *conn)
+                .await?;
+            Ok(())
+        }
+        .await;
+        finish(conn, result).await
+    }
+
+    async fn list_keys(&self, environment: Environment) -> Result<Vec<String>, StorageError> {
+        let ids = sqlx::query_scalar("SELECT lookup_id FROM api_keys WHERE environment = ?")
+            .bind(environment.as_str())
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(ids)
+    }
+
+    async fn record_usage(&self, usage: &[KeyUsage]) -> Result<(), StorageError> {
+        let mut tx = self.pool.begin().await?;
+        for usage in usage {
+            sqlx::query(
+                "UPDATE api_keys SET last_used_at = ?, last_used_ip = COALESCE(?, last_used_ip), \
+                 request_count = request_count + ? \
+                 WHERE lookup_id = ?",
+            )
+            .bind(usage.last_used_at)
+            .bind(usage.last_used_ip.map(|ip| ip.to_string()))
+            .bind(usage.requests as i64)
+            .bind(&usage.lookup_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_paired_key(&self, lookup_id: &str, paired_key: Option<&str>) -> Result<(), StorageError> {
+        let result = sqlx::query("UPDATE api_keys SET paired_key = ? WHERE lookup_id = ?")
+            .bind(paired_key)
+            .bind(lookup_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(StorageError::KeyNotFound);
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,12 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::Mutex;
 use thiserror::Error;
 use crate::validation::ApiKeyMetadata;
-use crate::generation::Environment;
-use crate::hashing::HashingError;
+use crate::generation::{Environment, ParsedApiKey};
+use crate::hashing::{Argon2Hasher, HashingError, KeyHasher};
+use crate::usage::KeyUsage;
 
 #[derive(Error, Debug)]
 pub enum StorageError {
@@ -15,99 +18,196 @@ pub enum StorageError {
     StorageError(String),
     #[error("Hash error: {0}")]
     HashError(#[from] HashingError),
+    #[error("Backend error: {0}")]
+    Backend(#[from] crate::error::StorageError),
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> Self {
+        let is_unique_violation = err
+            .as_database_error()
+            .map(|e| e.is_unique_violation())
+            .unwrap_or(false);
+
+        if is_unique_violation {
+            StorageError::KeyExists
+        } else {
+            StorageError::Backend(err.into())
+        }
+    }
 }
 
 /// Trait defining the storage interface for API keys
+///
+/// Implementations index keys on their [`ParsedApiKey::lookup_id`], so finding
+/// a key costs at most one hash verification regardless of how many keys are stored.
 #[async_trait::async_trait]
 pub trait ApiKeyStorage: Send + Sync + std::fmt::Debug {
     /// Store a new API key with its metadata
-    async fn store_key(&self, key: &str, metadata: ApiKeyMetadata) -> Result<(), StorageError>;
+    async fn store_key(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError>;
     
     /// Retrieve metadata for an API key
-    async fn get_metadata(&self, key: &str) -> Result<ApiKeyMetadata, StorageError>;
+    async fn get_metadata(&self, key: &ParsedApiKey) -> Result<ApiKeyMetadata, StorageError>;
     
     /// Update metadata for an existing API key
-    async fn update_metadata(&self, key: &str, metadata: ApiKeyMetadata) -> Result<(), StorageError>;
+    ///
+    /// Usage (`last_used_at`, `request_count` and `last_used_ip`) belongs to
+    /// [`record_usage`](Self::record_usage), so the stored values are kept and
+    /// the ones in `metadata` ignored. Metadata read before a usage flush
+    /// can't roll that flush back.
+    async fn update_metadata(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError>;
     
     /// Delete an API key
-    async fn delete_key(&self, key: &str) -> Result<(), StorageError>;
+    async fn delete_key(&self, key: &ParsedApiKey) -> Result<(), StorageError>;
     
-    /// List all API keys for an environment
+    /// List the lookup ids of all API keys for an environment
     async fn list_keys(&self, environment: Environment) -> Result<Vec<String>, StorageError>;
+
+    /// Apply a batch of usage to the keys it names, without verifying them again
+    ///
+    /// Sets `last_used_at`, adds to `request_count` and replaces `last_used_ip`
+    /// when the usage has one. Keys deleted since they were used are skipped.
+    async fn record_usage(&self, usage: &[KeyUsage]) -> Result<(), StorageError>;
+
+    /// Points the key with `lookup_id` at the key it is paired with, without verifying it
+    ///
+    /// Rotation uses this to keep a pair linked when one of its keys is replaced.
+    async fn set_paired_key(&self, lookup_id: &str, paired_key: Option<&str>) -> Result<(), StorageError>;
 }
 
 /// In-memory storage implementation for testing
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct InMemoryStorage {
+    /// Metadata indexed by lookup id
     keys: Mutex<HashMap<String, ApiKeyMetadata>>,
+    /// Verifies keys, upgrading other hashes to its own as their keys are looked up
+    hasher: Arc<dyn KeyHasher>,
 }
 
-impl InMemoryStorage {
-    pub fn new() -> Self {
+impl Default for InMemoryStorage {
+    fn default() -> Self {
         Self {
-            keys: Mutex::new(HashMap::new()),
+            keys: Mutex::default(),
+            hasher: Arc::new(Argon2Hasher::default()),
         }
     }
+}
 
-    async fn find_by_hash(&self, key: &str) -> Result<Option<(String, ApiKeyMetadata)>, StorageError> {
-        let keys = self.keys.lock().await;
-        let mut result = None;
-        
-        for (stored_key, metadata) in keys.iter() {
-            if metadata.verify_key(key).map_err(StorageError::HashError)? {
-                result = Some((stored_key.clone(), metadata.clone()));
-                break;
-            }
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Verifies keys with `hasher`, upgrading other hashes to it as their keys are looked up
+    pub fn with_hasher(mut self, hasher: Arc<dyn KeyHasher>) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    async fn find(&self, key: &ParsedApiKey) -> Result<Option<(String, ApiKeyMetadata)>, StorageError> {
+        let lookup_id = key.lookup_id().to_string();
+        let found = self.keys.lock().await.get(&lookup_id).cloned();
+
+        // Verify outside the lock; this is the only hash check per lookup
+        Ok(verify_found(found, key, &*self.hasher)?.map(|metadata| (lookup_id, metadata)))
+    }
+}
+
+/// Verifies `key` against the metadata stored under its lookup id, if any
+///
+/// A miss is checked against a dummy hash instead, so that every rejected
+/// key costs one hash verification and unknown lookup ids can't be told
+/// apart from known ones by how quickly they are turned away.
+pub(crate) fn verify_found(
+    found: Option<ApiKeyMetadata>,
+    key: &ParsedApiKey,
+    hasher: &dyn KeyHasher,
+) -> Result<Option<ApiKeyMetadata>, HashingError> {
+    let key = key.expose_secret();
+    match found {
+        Some(metadata) if metadata.verify_key_with(key, hasher)? => Ok(Some(metadata)),
+        Some(_) => Ok(None),
+        None => {
+            hasher.verify_dummy(key)?;
+            Ok(None)
         }
-        
-        Ok(result)
     }
 }
 
+/// Copies the usage `stored` has recorded onto `metadata`, for updates that must not overwrite it
+pub(crate) fn keep_usage(metadata: &mut ApiKeyMetadata, stored: &ApiKeyMetadata) {
+    metadata.last_used_at = stored.last_used_at;
+    metadata.last_used_ip = stored.last_used_ip;
+    metadata.request_count = stored.request_count;
+}
+
+/// Folds one batch entry into metadata held in memory
+pub(crate) fn apply_usage(metadata: &mut ApiKeyMetadata, usage: &KeyUsage) {
+    metadata.last_used_at = Some(usage.last_used_at);
+    metadata.last_used_ip = usage.last_used_ip.or(metadata.last_used_ip);
+    metadata.request_count += usage.requests;
+}
+
 #[async_trait::async_trait]
 impl ApiKeyStorage for InMemoryStorage {
-    async fn store_key(&self, key: &str, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
-        // Check if key exists first
-        if let Some(_) = self.find_by_hash(key).await? {
-            return Err(StorageError::KeyExists);
-        }
-        
-        // Store the key
+    async fn store_key(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
         let mut keys = self.keys.lock().await;
-        keys.insert(key.to_string(), metadata);
-        Ok(())
+        match keys.entry(key.lookup_id().to_string()) {
+            Entry::Occupied(_) => Err(StorageError::KeyExists),
+            Entry::Vacant(entry) => {
+                entry.insert(metadata);
+                Ok(())
+            }
+        }
     }
 
-    async fn get_metadata(&self, key: &str) -> Result<ApiKeyMetadata, StorageError> {
-        match self.find_by_hash(key).await? {
-            Some((_, metadata)) => Ok(metadata),
-            None => Err(StorageError::KeyNotFound),
+    async fn get_metadata(&self, key: &ParsedApiKey) -> Result<ApiKeyMetadata, StorageError> {
+        let (lookup_id, mut metadata) = match self.find(key).await? {
+            Some(found) => found,
+            None => return Err(StorageError::KeyNotFound),
+        };
+
+        let old_hash = metadata.key_hash.clone();
+        if metadata.rehash_if_needed(key.expose_secret(), &*self.hasher)? {
+            // Leave the entry alone if it was changed since we read it
+            if let Some(entry) = self.keys.lock().await.get_mut(&lookup_id) {
+                if entry.key_hash == old_hash {
+                    entry.key_hash = metadata.key_hash.clone();
+                }
+            }
         }
+        Ok(metadata)
     }
 
-    async fn update_metadata(&self, key: &str, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
+    async fn update_metadata(&self, key: &ParsedApiKey, mut metadata: ApiKeyMetadata) -> Result<(), StorageError> {
         // Find the key first
-        let stored_key = match self.find_by_hash(key).await? {
-            Some((k, _)) => k,
+        let lookup_id = match self.find(key).await? {
+            Some((id, _)) => id,
             None => return Err(StorageError::KeyNotFound),
         };
         
         // Update the metadata
-        let mut keys = self.keys.lock().await;
-        keys.insert(stored_key, metadata);
-        Ok(())
+        match self.keys.lock().await.get_mut(&lookup_id) {
+            Some(entry) => {
+                keep_usage(&mut metadata, entry);
+                *entry = metadata;
+                Ok(())
+            }
+            None => Err(StorageError::KeyNotFound),
+        }
     }
 
-    async fn delete_key(&self, key: &str) -> Result<(), StorageError> {
+    async fn delete_key(&self, key: &ParsedApiKey) -> Result<(), StorageError> {
         // Find the key first
-        let stored_key = match self.find_by_hash(key).await? {
-            Some((k, _)) => k,
+        let lookup_id = match self.find(key).await? {
+            Some((id, _)) => id,
             None => return Err(StorageError::KeyNotFound),
         };
         
         // Delete the key
         let mut keys = self.keys.lock().await;
-        keys.remove(&stored_key);
+        keys.remove(&lookup_id);
         Ok(())
     }
 
@@ -116,7 +216,27 @@ impl ApiKeyStorage for InMemoryStorage {
         Ok(keys
             .iter()
             .filter(|(_, metadata)| metadata.environment == environment)
-            .map(|(key, _)| key.clone())
+            .map(|(lookup_id, _)| lookup_id.clone())
             .collect())
     }
+
+    async fn record_usage(&self, usage: &[KeyUsage]) -> Result<(), StorageError> {
+        let mut keys = self.keys.lock().await;
+        for usage in usage {
+            if let Some(metadata) = keys.get_mut(&usage.lookup_id) {
+                apply_usage(metadata, usage);
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_paired_key(&self, lookup_id: &str, paired_key: Option<&str>) -> Result<(), StorageError> {
+        match self.keys.lock().await.get_mut(lookup_id) {
+            Some(metadata) => {
+                metadata.paired_key = paired_key.map(str::to_string);
+                Ok(())
+            }
+            None => Err(StorageError::KeyNotFound),
+        }
+    }
 }
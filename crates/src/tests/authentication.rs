@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use crate::authentication::*;
+use crate::authorization::Scope;
+use crate::error::ApiKeyError;
+use crate::generation::{generate_api_key, generate_api_key_with_scopes, Environment, KeyKind, ParsedApiKey};
+use crate::hashing::{Argon2Hasher, HashingConfig, HashingError, KeyHash, KeyHasher};
+use crate::storage::{ApiKeyStorage, InMemoryStorage};
+use crate::validation::ApiKeyMetadata;
+
+/// Stores a freshly generated key after letting `edit` change its metadata
+async fn store_key(storage: &InMemoryStorage, edit: impl FnOnce(&mut ApiKeyMetadata)) -> ParsedApiKey {
+    let (key, mut metadata) = generate_api_key(Environment::Test).unwrap();
+    edit(&mut metadata);
+    storage.store_key(&key, metadata).await.unwrap();
+    key
+}
+
+/// Counts verifications on the way to a cheap Argon2 hasher
+#[derive(Debug)]
+struct CountingHasher {
+    inner: Argon2Hasher,
+    verifies: AtomicUsize,
+}
+
+impl KeyHasher for CountingHasher {
+    fn hash(&self, key: &str) -> Result<KeyHash, HashingError> {
+        self.inner.hash(key)
+    }
+
+    fn verify(&self, hash: &KeyHash, key: &str) -> Result<bool, HashingError> {
+        self.verifies.fetch_add(1, Ordering::SeqCst);
+        self.inner.verify(hash, key)
+    }
+
+    fn needs_rehash(&self, hash: &KeyHash) -> Result<bool, HashingError> {
+        self.inner.needs_rehash(hash)
+    }
+}
+
+#[tokio::test]
+async fn test_authenticate_valid_key() {
+    let storage = InMemoryStorage::new();
+    let (key, mut metadata) = generate_api_key_with_scopes(Environment::Live, vec![Scope::KeysRead]).unwrap();
+    metadata.expires_at = Some(Utc::now() + Duration::days(30));
+    storage.store_key(&key, metadata.clone()).await.unwrap();
+
+    let authenticated = authenticate(&storage, key.expose_secret()).await.unwrap();
+    assert_eq!(authenticated.lookup_id, metadata.lookup_id);
+    assert_eq!(authenticated.kind, KeyKind::Secret);
+    assert_eq!(authenticated.environment, Environment::Live);
+    assert_eq!(authenticated.scopes, vec![Scope::KeysRead]);
+    let expires_in = authenticated.expires_in.unwrap();
+    assert!(expires_in > Duration::days(29) && expires_in <= Duration::days(30));
+
+    // Nothing is written; usage is tracked by UsageTracker
+    let stored = storage.get_metadata(&key).await.unwrap();
+    assert_eq!(stored.last_used_at, None);
+    assert_eq!(stored.request_count, 0);
+    assert_eq!(authenticated.metadata.request_count, 0);
+}
+
+#[tokio::test]
+async fn test_authenticate_without_expiry() {
+    let storage = InMemoryStorage::new();
+    let key = store_key(&storage, |_| {}).await;
+    assert_eq!(authenticate(&storage, key.expose_secret()).await.unwrap().expires_in, None);
+}
+
+#[tokio::test]
+async fn test_authenticate_failures() {
+    let storage = InMemoryStorage::new();
+    let revoked = store_key(&storage, |m| m.is_revoked = true).await;
+    let inactive = store_key(&storage, |m| m.is_active = false).await;
+    let expired = store_key(&storage, |m| m.expires_at = Some(Utc::now() - Duration::seconds(1))).await;
+    let (unknown, _) = generate_api_key(Environment::Test).unwrap();
+
+    assert_eq!(authenticate(&storage, "not a key").await.unwrap_err(), ApiKeyError::InvalidFormat);
+    assert_eq!(authenticate(&storage, unknown.expose_secret()).await.unwrap_err(), ApiKeyError::NotFound);
+    assert_eq!(authenticate(&storage, revoked.expose_secret()).await.unwrap_err(), ApiKeyError::Revoked);
+    assert_eq!(authenticate(&storage, inactive.expose_secret()).await.unwrap_err(), ApiKeyError::Inactive);
+    assert_eq!(authenticate(&storage, expired.expose_secret()).await.unwrap_err(), ApiKeyError::Expired);
+
+    // Rejected keys aren't marked as used
+    assert_eq!(storage.get_metadata(&revoked).await.unwrap().last_used_at, None);
+}
+
+#[tokio::test]
+async fn test_authenticate_verifies_hash_once() {
+    let hasher = Arc::new(CountingHasher {
+        inner: Argon2Hasher::new(HashingConfig {
+            memory_cost: 1024,
+            time_cost: 1,
+            ..HashingConfig::default()
+        }),
+        verifies: AtomicUsize::new(0),
+    });
+    let storage: Arc<dyn ApiKeyStorage> = Arc::new(InMemoryStorage::new().with_hasher(hasher.clone()));
+
+    let (key, mut metadata) = generate_api_key(Environment::Test).unwrap();
+    metadata.key_hash = hasher.hash(key.expose_secret()).unwrap().to_string();
+    storage.store_key(&key, metadata).await.unwrap();
+
+    authenticate(&*storage, key.expose_secret()).await.unwrap();
+    assert_eq!(hasher.verifies.load(Ordering::SeqCst), 1);
+}
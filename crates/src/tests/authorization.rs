@@ -0,0 +1,75 @@
+use crate::authorization::*;
+use crate::generation::{generate_api_key, generate_api_key_with_scopes, generate_publishable_key, Environment};
+use crate::validation::ApiKeyMetadata;
+
+#[test]
+fn test_default_key_has_all_scopes() {
+    let (_, metadata) = generate_api_key(Environment::Test).unwrap();
+    assert_eq!(metadata.scopes, Scope::all());
+    assert!(authorize_all(&metadata, &Scope::all()).is_ok());
+}
+
+#[test]
+fn test_read_only_key() {
+    let (_, metadata) = generate_api_key_with_scopes(Environment::Test, vec![Scope::KeysRead]).unwrap();
+    assert!(authorize(&metadata, Scope::KeysRead).is_ok());
+    assert_eq!(
+        authorize(&metadata, Scope::KeysWrite),
+        Err(AuthorizationError::InsufficientScope(Scope::KeysWrite))
+    );
+    assert_eq!(
+        authorize_all(&metadata, &[Scope::KeysRead, Scope::AuditRead]),
+        Err(AuthorizationError::InsufficientScope(Scope::AuditRead))
+    );
+}
+
+#[test]
+fn test_insufficient_scope_message() {
+    let err = AuthorizationError::InsufficientScope(Scope::AuditRead);
+    assert_eq!(err.to_string(), "Insufficient scope: audit:read required");
+}
+
+#[test]
+fn test_scope_parsing() {
+    for scope in Scope::all() {
+        assert_eq!(Scope::try_from(scope.as_str()), Ok(scope));
+    }
+    assert_eq!(
+        Scope::try_from("keys:delete"),
+        Err(AuthorizationError::UnknownScope("keys:delete".to_string()))
+    );
+}
+
+#[test]
+fn test_scope_serialization() {
+    let json = serde_json::to_string(&vec![Scope::KeysRead, Scope::AuditRead]).unwrap();
+    assert_eq!(json, r#"["keys:read","audit:read"]"#);
+}
+
+#[test]
+fn test_metadata_without_scopes_keeps_full_access() {
+    let (_, metadata) = generate_api_key_with_scopes(Environment::Test, vec![]).unwrap();
+    let mut json = serde_json::to_value(&metadata).unwrap();
+    json.as_object_mut().unwrap().remove("scopes");
+
+    let metadata: ApiKeyMetadata = serde_json::from_value(json).unwrap();
+    assert_eq!(metadata.scopes, Scope::all());
+}
+
+#[test]
+fn test_publishable_key_is_limited_to_public_read() {
+    let (_, metadata) = generate_publishable_key(Environment::Live).unwrap();
+    assert_eq!(metadata.scopes, vec![Scope::PublicRead]);
+    assert!(authorize(&metadata, Scope::PublicRead).is_ok());
+    assert_eq!(
+        authorize(&metadata, Scope::KeysWrite),
+        Err(AuthorizationError::InsufficientScope(Scope::KeysWrite))
+    );
+}
+
+#[test]
+fn test_secret_key_implies_public_read() {
+    let (_, metadata) = generate_api_key_with_scopes(Environment::Test, vec![Scope::AuditRead]).unwrap();
+    assert!(authorize(&metadata, Scope::PublicRead).is_ok());
+    assert_eq!(Scope::try_from("public:read"), Ok(Scope::PublicRead));
+}
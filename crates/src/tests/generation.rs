@@ -1,28 +1,45 @@
-use crate::generation::{generate_api_key, validate_key_format, Environment};
+use crate::generation::{
+    checksum, generate_api_key, generate_key_pair, generate_publishable_key, parse_prefix,
+    validate_key_format, Environment, KeyAlphabet, KeyFormat, KeyGenerationError, KeyKind,
+    CHECKSUM_LEN, LOOKUP_ID_LEN, MAX_BRAND_LEN, MAX_ENVIRONMENT_LEN,
+};
+use crate::authorization::Scope;
+use crate::generation::{KeyGenerator, LegacyKeys, ParsedApiKey};
+use crate::rate_limit::FixedTimeProvider;
+use rand::{rngs::StdRng, SeedableRng};
+use std::sync::Arc;
+use crate::validation::{
+    validate_api_key, validate_api_key_with_format, validate_parsed_api_key, ApiKeyMetadata,
+    ApiKeyValidationError,
+};
+use crate::hashing::{sha256_hex, HashingError};
 
 #[test]
 fn test_generate_api_key() {
     let (key, metadata) = generate_api_key(Environment::Test).unwrap();
+    let key = key.expose_secret();
     assert!(key.starts_with("tronch_sk_test_"));
-    assert_eq!(key.len(), 52);
-    assert!(metadata.verify_key(&key).unwrap());
+    assert_eq!(key.len(), 66);
+    assert!(metadata.verify_key(key).unwrap());
     assert_eq!(metadata.environment, Environment::Test);
 }
 
 #[test]
 fn test_generate_api_key_live() {
     let (key, metadata) = generate_api_key(Environment::Live).unwrap();
+    let key = key.expose_secret();
     assert!(key.starts_with("tronch_sk_live_"));
-    assert_eq!(key.len(), 52);
-    assert!(metadata.verify_key(&key).unwrap());
+    assert_eq!(key.len(), 66);
+    assert!(metadata.verify_key(key).unwrap());
     assert_eq!(metadata.environment, Environment::Live);
 }
 
 #[test]
 fn test_validate_api_key_format() {
     let (key, _) = generate_api_key(Environment::Test).unwrap();
-    assert!(validate_key_format(&key, None).is_ok());
-    assert!(validate_key_format(&key, Some(Environment::Test)).is_ok());
+    let key = key.expose_secret();
+    assert!(validate_key_format(key, None).is_ok());
+    assert!(validate_key_format(key, Some(Environment::Test)).is_ok());
 }
 
 #[test]
@@ -44,5 +61,301 @@ fn test_validate_api_key_format_invalid_chars() {
 #[test]
 fn test_validate_api_key_format_invalid() {
     let (key, _) = generate_api_key(Environment::Test).unwrap();
-    assert!(validate_key_format(&key, Some(Environment::Live)).is_err());
-}
\ No newline at end of file
+    let key = key.expose_secret();
+    assert!(validate_key_format(key, Some(Environment::Live)).is_err());
+}
+
+#[test]
+fn test_lookup_id_of_generated_key() {
+    let (key, metadata) = generate_api_key(Environment::Live).unwrap();
+    let id = key.lookup_id();
+    assert_eq!(id.len(), LOOKUP_ID_LEN);
+    assert_eq!(id, &key.expose_secret()["tronch_sk_live_".len().."tronch_sk_live_".len() + LOOKUP_ID_LEN]);
+    assert_eq!(id, metadata.lookup_id);
+}
+
+#[test]
+fn test_legacy_key_lookup_id_is_keyed() {
+    let legacy = LegacyKeys::new(Environment::Live, [1u8; 32]).unwrap();
+    let key = ParsedApiKey::legacy("legacy_key", &legacy);
+    assert!(key.is_legacy());
+    assert_eq!(key.environment(), Environment::Live);
+    assert_eq!(key.secret(), "legacy_key");
+    assert_eq!(key.prefix(), "");
+    assert_eq!(key.checksum(), "");
+
+    // The id can't be computed from the key alone
+    assert_ne!(key.lookup_id(), sha256_hex("legacy_key"));
+    assert_eq!(key.lookup_id(), ParsedApiKey::legacy("legacy_key", &legacy).lookup_id());
+    let other = LegacyKeys::new(Environment::Live, [2u8; 32]).unwrap();
+    assert_ne!(key.lookup_id(), ParsedApiKey::legacy("legacy_key", &other).lookup_id());
+}
+
+#[test]
+fn test_legacy_keys_reject_short_pepper() {
+    assert!(matches!(
+        LegacyKeys::new(Environment::Live, [1u8; 16]),
+        Err(HashingError::InvalidPepper(_))
+    ));
+}
+
+#[test]
+fn test_generate_publishable_key() {
+    let (parsed, metadata) = generate_publishable_key(Environment::Live).unwrap();
+    let key = parsed.expose_secret();
+    assert!(key.starts_with("tronch_pk_live_"));
+    assert_eq!(key.len(), 66);
+    assert!(validate_key_format(key, Some(Environment::Live)).is_ok());
+    assert_eq!(parse_prefix(key), Some((KeyKind::Publishable, Environment::Live)));
+    assert_eq!(parsed.kind(), KeyKind::Publishable);
+    assert_eq!(metadata.kind, KeyKind::Publishable);
+    assert_eq!(metadata.lookup_id, parsed.lookup_id());
+}
+
+#[test]
+fn test_generate_key_pair() {
+    let pair = generate_key_pair(Environment::Test).unwrap();
+    assert!(pair.secret_key.expose_secret().starts_with("tronch_sk_test_"));
+    assert!(pair.publishable_key.expose_secret().starts_with("tronch_pk_test_"));
+    assert_eq!(pair.secret_metadata.kind, KeyKind::Secret);
+    assert_eq!(pair.secret_metadata.paired_key, Some(pair.publishable_metadata.lookup_id.clone()));
+    assert_eq!(pair.publishable_metadata.paired_key, Some(pair.secret_metadata.lookup_id.clone()));
+}
+
+#[test]
+fn test_checksum_rejects_typos() {
+    let (key, _) = generate_api_key(Environment::Live).unwrap();
+    let key = key.expose_secret();
+
+    // Swap one character in the random segment for a different alphanumeric one
+    let pos = key.len() - CHECKSUM_LEN - 1;
+    let replacement = if &key[pos..pos + 1] == "a" { "b" } else { "a" };
+    let typo = format!("{}{}{}", &key[..pos], replacement, &key[pos + 1..]);
+
+    assert!(matches!(validate_key_format(&typo, None), Err(KeyGenerationError::InvalidChecksum)));
+    assert!(validate_key_format(&key[..key.len() - 1], None).is_err());
+}
+
+#[test]
+fn test_checksum_is_fixed_width_base62() {
+    let (key, _) = generate_api_key(Environment::Test).unwrap();
+    let key = key.expose_secret();
+    let (body, sum) = key.split_at(key.len() - CHECKSUM_LEN);
+    assert_eq!(checksum(body), sum);
+
+    for body in ["", "a", "tronch_sk_test_"] {
+        let sum = checksum(body);
+        assert_eq!(sum.len(), CHECKSUM_LEN);
+        assert!(sum.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+}
+
+#[test]
+fn test_custom_brand_and_length() {
+    let format = KeyFormat::new("acme").with_length(64);
+    let (parsed, metadata) = format.generate(KeyKind::Publishable, Environment::Test, vec![Scope::PublicRead]).unwrap();
+    let key = parsed.expose_secret();
+    assert!(key.starts_with("acme_pk_test_"));
+    assert_eq!(key.len(), 64);
+    assert_eq!(metadata.kind, KeyKind::Publishable);
+    assert_eq!(metadata.lookup_id, parsed.lookup_id());
+    assert_eq!(metadata.lookup_id, key["acme_pk_test_".len().."acme_pk_test_".len() + LOOKUP_ID_LEN]);
+
+    assert!(format.validate_key(key, Some(Environment::Test)).is_ok());
+    assert!(validate_api_key_with_format(key, &metadata, &format).is_ok());
+
+    // The default format does not accept another brand's keys
+    assert!(validate_key_format(key, None).is_err());
+    assert!(matches!(validate_api_key(key, &metadata), Err(ApiKeyValidationError::InvalidFormat)));
+}
+
+#[test]
+fn test_crockford_base32_alphabet() {
+    let format = KeyFormat::default()
+        .with_length(60)
+        .with_alphabet(KeyAlphabet::CrockfordBase32);
+    let (key, _) = format.generate(KeyKind::Secret, Environment::Live, Scope::all()).unwrap();
+    let key = key.expose_secret();
+    let random = &key["tronch_sk_live_".len()..];
+    assert!(random.chars().all(|c| "0123456789ABCDEFGHJKMNPQRSTVWXYZ".contains(c)));
+    assert!(format.validate_key(key, None).is_ok());
+
+    let lower = format!("tronch_sk_live_{}", random.to_lowercase());
+    assert!(format.validate_key(&lower, None).is_err());
+}
+
+#[test]
+fn test_format_entropy_checks() {
+    assert!(KeyFormat::default().validate().is_ok());
+
+    // The default keeps as much secret as the original 52-character keys
+    assert!(KeyFormat::default().secret_bits() > 170.0);
+
+    // 52 characters of base32 leaves only 75 secret bits
+    let short = KeyFormat::default()
+        .with_length(52)
+        .with_alphabet(KeyAlphabet::CrockfordBase32);
+    assert!(matches!(short.validate(), Err(KeyGenerationError::UnsafeFormat(_))));
+    assert!(short.generate(KeyKind::Secret, Environment::Test, Scope::all()).is_err());
+
+    let long_brand = KeyFormat::new("averyverylongbrandname");
+    assert!(matches!(long_brand.validate(), Err(KeyGenerationError::UnsafeFormat(_))));
+    assert!(long_brand.with_length(80).validate().is_ok());
+
+    assert!(KeyFormat::new("").validate().is_err());
+    assert!(KeyFormat::new("acme_co").validate().is_err());
+
+    // Brands are capped so that prefixes fit storage columns, whatever the length
+    assert!(KeyFormat::new("a".repeat(MAX_BRAND_LEN)).with_length(200).validate().is_ok());
+    let too_long = KeyFormat::new("a".repeat(MAX_BRAND_LEN + 1)).with_length(200);
+    assert!(matches!(too_long.validate(), Err(KeyGenerationError::UnsafeFormat(_))));
+    let region = Environment::register("a".repeat(MAX_ENVIRONMENT_LEN).as_str()).unwrap();
+    assert!(KeyFormat::new("a".repeat(MAX_BRAND_LEN)).prefix(KeyKind::Secret, region).len() <= 64);
+}
+
+#[test]
+fn test_custom_environment() {
+    let staging = Environment::register("staging").unwrap();
+    assert_eq!(Environment::register("staging").unwrap(), staging);
+    assert_eq!(Environment::try_from("Staging").unwrap(), staging);
+    assert!(Environment::all().contains(&staging));
+
+    let (key, metadata) = generate_api_key(staging).unwrap();
+    let key = key.expose_secret();
+    assert!(key.starts_with("tronch_sk_staging_"));
+    assert_eq!(key.len(), KeyFormat::default().key_len(staging));
+    assert_eq!(metadata.environment, staging);
+    assert_eq!(parse_prefix(key), Some((KeyKind::Secret, staging)));
+    assert!(validate_key_format(key, Some(staging)).is_ok());
+    assert!(validate_key_format(key, Some(Environment::Live)).is_err());
+    assert!(validate_api_key(key, &metadata).is_ok());
+}
+
+#[test]
+fn test_every_environment_round_trips() {
+    let staging = Environment::register("staging").unwrap();
+    assert_eq!(staging.as_str(), "staging");
+    for env in Environment::all() {
+        let json = serde_json::to_string(&env).unwrap();
+        assert_eq!(serde_json::from_str::<Environment>(&json).unwrap(), env);
+        assert_eq!(Environment::try_from(env.as_str()).unwrap(), env);
+    }
+}
+
+#[test]
+fn test_register_rejects_bad_names() {
+    for name in ["", "test", "live", "Staging", "eu_west", &"x".repeat(33)] {
+        assert!(matches!(Environment::register(name), Err(KeyGenerationError::InvalidEnvironment)));
+    }
+    assert!(Environment::try_from("never-registered").is_err());
+}
+
+#[test]
+fn test_environment_serde() {
+    assert_eq!(serde_json::to_string(&Environment::Test).unwrap(), "\"Test\"");
+    assert_eq!(serde_json::from_str::<Environment>("\"Live\"").unwrap(), Environment::Live);
+
+    let sandbox = Environment::register("sandbox").unwrap();
+    let json = serde_json::to_string(&sandbox).unwrap();
+    assert_eq!(json, "\"sandbox\"");
+    assert_eq!(serde_json::from_str::<Environment>(&json).unwrap(), sandbox);
+
+    assert!(serde_json::from_str::<Environment>("\"unheard-of\"").is_err());
+}
+
+fn seeded_generator(seed: u64) -> KeyGenerator<StdRng> {
+    KeyGenerator::new(StdRng::seed_from_u64(seed), Arc::new(FixedTimeProvider(1_712_345_678)))
+}
+
+#[test]
+fn test_seeded_generator_is_reproducible() {
+    let (key, metadata) = seeded_generator(42).generate(KeyKind::Secret, Environment::Test, Scope::all()).unwrap();
+    let key = key.expose_secret();
+    // StdRng's output is only stable within a rand release, so this may need updating with it
+    assert_eq!(key, "tronch_sk_test_87654321WXdP2Pjq80vVO8WD0W3eqUV3LRnCOeQtvreDZ3s63Y3");
+    assert_eq!(metadata.created_at.timestamp(), 1_712_345_678);
+    assert!(metadata.verify_key(key).unwrap());
+
+    // Same seed, same keys; the next key from one generator differs from the first
+    let mut generator = seeded_generator(42);
+    let first = generator.generate(KeyKind::Secret, Environment::Test, Scope::all()).unwrap().0;
+    let second = generator.generate(KeyKind::Secret, Environment::Test, Scope::all()).unwrap().0;
+    assert_eq!(first.expose_secret(), key);
+    assert_ne!(second.expose_secret(), key);
+
+    let other = seeded_generator(43).generate(KeyKind::Secret, Environment::Test, Scope::all()).unwrap().0;
+    assert_ne!(other.expose_secret(), key);
+}
+
+#[test]
+fn test_seeded_key_pair() {
+    let pair = seeded_generator(7).generate_key_pair(Environment::Live).unwrap();
+    let again = seeded_generator(7).generate_key_pair(Environment::Live).unwrap();
+    assert_eq!(pair.secret_key, again.secret_key);
+    assert_eq!(pair.publishable_key, again.publishable_key);
+    assert_eq!(&pair.secret_key.expose_secret()["tronch_sk_live_".len().."tronch_sk_live_".len() + 8], "87654321");
+}
+
+#[test]
+fn test_parsed_key_segments() {
+    let (key, metadata) = seeded_generator(42).generate(KeyKind::Secret, Environment::Test, Scope::all()).unwrap();
+    let key = key.expose_secret();
+    let parsed: ParsedApiKey = key.parse().unwrap();
+
+    assert_eq!(parsed.kind(), KeyKind::Secret);
+    assert_eq!(parsed.environment(), Environment::Test);
+    assert_eq!(parsed.prefix(), "tronch_sk_test_");
+    assert_eq!(parsed.timestamp(), "87654321");
+    assert_eq!(parsed.lookup_id(), "87654321WXdP2Pjq");
+    assert_eq!(parsed.secret(), "80vVO8WD0W3eqUV3LRnCOeQtvreDZ");
+    assert_eq!(parsed.checksum(), "3s63Y3");
+    assert_eq!(parsed.lookup_id(), metadata.lookup_id);
+    assert_eq!(parsed.expose_secret(), key);
+    assert!(validate_parsed_api_key(&parsed, &metadata).is_ok());
+}
+
+#[test]
+fn test_parse_errors() {
+    let (key, _) = generate_api_key(Environment::Live).unwrap();
+    let key = key.expose_secret();
+    assert!(matches!(ParsedApiKey::parse(&key[..key.len() - 1]), Err(KeyGenerationError::InvalidFormat)));
+    assert!(matches!("test_key".parse::<ParsedApiKey>(), Err(KeyGenerationError::InvalidFormat)));
+
+    let upper = key.replacen("tronch", "TRONCH", 1);
+    assert!(ParsedApiKey::parse(&upper).is_err());
+}
+
+#[test]
+fn test_parse_custom_format() {
+    let format = KeyFormat::new("acme").with_length(60).with_alphabet(KeyAlphabet::CrockfordBase32);
+    let (generated, _) = format.generate(KeyKind::Publishable, Environment::Live, vec![]).unwrap();
+    let key = generated.expose_secret();
+    let parsed = format.parse(key).unwrap();
+    assert_eq!(parsed.kind(), KeyKind::Publishable);
+    assert_eq!(parsed.prefix(), "acme_pk_live_");
+    assert_eq!(parsed.checksum().len(), KeyAlphabet::CrockfordBase32.checksum_len());
+    assert_eq!(parsed, generated);
+    assert!(ParsedApiKey::parse(key).is_err());
+}
+
+#[test]
+fn test_parse_rejects_unsafe_format() {
+    // Too short to hold a lookup id and a secret; splitting it would go out of bounds
+    let format = KeyFormat::new("x").with_length(30);
+    let body = "x_sk_test_12345678901234";
+    let key = format!("{}{}", body, checksum(body));
+    assert_eq!(key.len(), 30);
+    assert!(matches!(format.parse(&key), Err(KeyGenerationError::UnsafeFormat(_))));
+    assert!(format.validate_key(&key, None).is_err());
+}
+
+#[test]
+fn test_validate_reports_environment_mismatch() {
+    let (key, _) = generate_api_key(Environment::Test).unwrap();
+    let mut metadata = ApiKeyMetadata::new(&key).unwrap();
+    metadata.environment = Environment::Live;
+    assert!(matches!(
+        validate_api_key(key.expose_secret(), &metadata),
+        Err(ApiKeyValidationError::EnvironmentMismatch)
+    ));
+}
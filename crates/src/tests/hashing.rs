@@ -1,4 +1,8 @@
-use crate::hashing::KeyHash;
+use crate::hashing::{
+    sha256_hex, Argon2Hasher, HashAlgorithm, HashingConfig, HmacSha256Hasher, KeyHash, KeyHasher,
+    PepperSet,
+};
+use argon2::Algorithm;
 
 #[test]
 fn test_hash_creation() {
@@ -30,6 +34,8 @@ fn test_hash_serialization() {
     
     // Serialize and deserialize
     let serialized = hash.to_string();
+    assert!(serialized.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+    assert!(!KeyHash::is_legacy_encoding(&serialized));
     let deserialized = KeyHash::from_string(&serialized).unwrap();
     
     // Should still verify after serialization
@@ -46,6 +52,29 @@ fn test_invalid_hash_format() {
     
     // Empty string should error
     assert!(KeyHash::from_string("").is_err());
+
+    // Malformed PHC strings should error
+    assert!(KeyHash::from_string("$argon2id$v=19$garbage").is_err());
+    assert!(KeyHash::from_string("$hmac-sha256$pepper=x$AAAAAAAAAAAAAAAAAAAAAA").is_err());
+    assert!(KeyHash::from_string("$sha256$not*b64").is_err());
+}
+
+#[test]
+fn test_legacy_encodings_accepted() {
+    let key = "key";
+
+    // Argon2 hashes were stored behind a copy of their salt
+    let argon2 = KeyHash::new(key).unwrap().to_string();
+    let salt = argon2.split('$').nth(4).unwrap();
+    let legacy = format!("{}:{}", salt, argon2);
+    assert!(KeyHash::is_legacy_encoding(&legacy));
+    let hash = KeyHash::from_string(&legacy).unwrap();
+    assert_eq!(hash.to_string(), argon2);
+    assert!(hash.verify(key).unwrap());
+
+    assert!(KeyHash::from_string("salt:not-a-phc-string").is_err());
+    // Only Argon2 hashes were ever stored that way
+    assert!(KeyHash::from_string(&format!("sha256:{}", sha256_hex(key))).is_err());
 }
 
 #[test]
@@ -76,4 +105,200 @@ fn test_hash_with_unicode() {
     let key = "tronch_sk_test_20240101🌍🌎🌏abcdef123456789";
     let hash = KeyHash::new(key).unwrap();
     assert!(hash.verify(key).unwrap());
-} 
\ No newline at end of file
+}
+
+#[test]
+fn test_legacy_digest() {
+    let digest = sha256_hex("legacy-key");
+    let hash = KeyHash::from_legacy_digest(&digest).unwrap();
+    assert!(hash.verify("legacy-key").unwrap());
+    assert!(!hash.verify("other-key").unwrap());
+
+    // Survives a storage round trip
+    let deserialized = KeyHash::from_string(&hash.to_string()).unwrap();
+    assert!(deserialized.verify("legacy-key").unwrap());
+
+    assert!(KeyHash::from_legacy_digest("not-a-digest").is_err());
+}
+
+#[test]
+fn test_configured_parameters() {
+    let key = "tronch_sk_test_20240101abcdef1234567890abcdef1234567";
+    let config = HashingConfig {
+        algorithm: Algorithm::Argon2i,
+        memory_cost: 2048,
+        time_cost: 3,
+        parallelism: 2,
+    };
+    let hash = KeyHash::with_config(key, &config).unwrap();
+    assert!(hash.to_string().contains("$argon2i$v=19$m=2048,t=3,p=2$"));
+    assert!(hash.verify(key).unwrap());
+
+    assert!(!hash.needs_rehash(&config).unwrap());
+    assert!(hash.needs_rehash(&HashingConfig::default()).unwrap());
+    assert!(hash.needs_rehash(&HashingConfig { time_cost: 4, ..config }).unwrap());
+    assert!(hash.needs_rehash(&HashingConfig { algorithm: Algorithm::Argon2id, ..config }).unwrap());
+}
+
+#[test]
+fn test_default_hash_needs_no_rehash() {
+    let hash = KeyHash::new("key").unwrap();
+    assert!(!hash.needs_rehash(&HashingConfig::default()).unwrap());
+
+    let legacy = KeyHash::from_legacy_digest(&crate::hashing::sha256_hex("key")).unwrap();
+    assert!(legacy.needs_rehash(&HashingConfig::default()).unwrap());
+}
+
+#[test]
+fn test_invalid_parameters() {
+    let config = HashingConfig {
+        parallelism: 0,
+        ..HashingConfig::default()
+    };
+    assert!(KeyHash::with_config("key", &config).is_err());
+}
+
+#[test]
+fn test_hmac_hasher() {
+    let key = "tronch_sk_test_20240101abcdef1234567890abcdef1234567";
+    let hasher = HmacSha256Hasher::new(*b"0123456789abcdef0123456789abcdef").unwrap();
+    let hash = hasher.hash(key).unwrap();
+
+    // Deterministic, and tagged so the algorithm survives storage
+    assert_eq!(hash.to_string(), hasher.hash(key).unwrap().to_string());
+    assert!(hash.to_string().starts_with("$hmac-sha256$pepper=1$"));
+    let stored = KeyHash::from_string(&hash.to_string()).unwrap();
+    assert_eq!(stored.algorithm(), HashAlgorithm::HmacSha256);
+
+    assert!(hasher.verify(&stored, key).unwrap());
+    assert!(!hasher.verify(&stored, "wrong_key").unwrap());
+    assert!(!hasher.needs_rehash(&stored).unwrap());
+
+    // A different pepper doesn't verify, and neither does anything without one
+    let other = HmacSha256Hasher::new([0u8; 32]).unwrap();
+    assert!(!other.verify(&stored, key).unwrap());
+    assert!(stored.verify(key).is_err());
+    assert!(Argon2Hasher::default().needs_rehash(&stored).unwrap());
+}
+
+#[test]
+fn test_hmac_hasher_verifies_older_hashes() {
+    let key = "key";
+    let hasher = HmacSha256Hasher::new([1u8; 32]).unwrap();
+
+    let argon2 = KeyHash::new(key).unwrap();
+    assert_eq!(argon2.algorithm(), HashAlgorithm::Argon2);
+    assert!(hasher.verify(&argon2, key).unwrap());
+    assert!(hasher.needs_rehash(&argon2).unwrap());
+
+    let legacy = KeyHash::from_legacy_digest(&sha256_hex(key)).unwrap();
+    assert_eq!(legacy.algorithm(), HashAlgorithm::LegacySha256);
+    assert!(hasher.verify(&legacy, key).unwrap());
+    assert!(hasher.needs_rehash(&legacy).unwrap());
+}
+
+#[test]
+fn test_short_pepper_rejected() {
+    assert!(HmacSha256Hasher::new(vec![0u8; 31]).is_err());
+    assert!(!format!("{:?}", HmacSha256Hasher::new([9u8; 32]).unwrap()).contains('9'));
+}
+
+fn weak_config() -> HashingConfig {
+    HashingConfig {
+        memory_cost: 1024,
+        time_cost: 1,
+        ..HashingConfig::default()
+    }
+}
+
+#[test]
+fn test_hmac_pepper_rotation() {
+    let key = "key";
+    let old = HmacSha256Hasher::from_peppers(PepperSet::new(1, [1u8; 32]).unwrap());
+    let hash = old.hash(key).unwrap();
+
+    let rotated = HmacSha256Hasher::from_peppers(
+        PepperSet::new(1, [1u8; 32]).unwrap().with_version(2, [2u8; 32]).unwrap(),
+    );
+    assert!(rotated.verify(&hash, key).unwrap());
+    assert!(rotated.needs_rehash(&hash).unwrap());
+
+    let new_hash = rotated.hash(key).unwrap();
+    assert_eq!(new_hash.pepper_version().unwrap(), Some(2));
+    assert!(!rotated.needs_rehash(&new_hash).unwrap());
+
+    // Once version 1 is dropped its hashes no longer verify
+    assert!(old.verify(&new_hash, key).is_err());
+}
+
+#[test]
+fn test_argon2_pepper() {
+    let key = "key";
+    let peppers = PepperSet::new(3, [3u8; 32]).unwrap();
+    let hasher = Argon2Hasher::new(weak_config()).with_peppers(peppers.clone());
+    let hash = KeyHash::from_string(&hasher.hash(key).unwrap().to_string()).unwrap();
+
+    assert_eq!(hash.algorithm(), HashAlgorithm::Argon2);
+    assert_eq!(hash.pepper_version().unwrap(), Some(3));
+    assert!(hasher.verify(&hash, key).unwrap());
+    assert!(!hasher.verify(&hash, "wrong_key").unwrap());
+    assert!(!hasher.needs_rehash(&hash).unwrap());
+
+    // Without the pepper the hash can't be checked at all
+    assert!(hash.verify(key).is_err());
+    assert!(Argon2Hasher::new(weak_config()).verify(&hash, key).is_err());
+    let wrong = Argon2Hasher::new(weak_config()).with_peppers(PepperSet::new(3, [4u8; 32]).unwrap());
+    assert!(!wrong.verify(&hash, key).unwrap());
+
+    // Unpeppered hashes still verify, and are upgraded
+    let plain = KeyHash::with_config(key, &weak_config()).unwrap();
+    assert!(hasher.verify(&plain, key).unwrap());
+    assert!(hasher.needs_rehash(&plain).unwrap());
+
+    let rotated = Argon2Hasher::new(weak_config())
+        .with_peppers(peppers.with_version(4, [5u8; 32]).unwrap());
+    assert!(rotated.verify(&hash, key).unwrap());
+    assert!(rotated.needs_rehash(&hash).unwrap());
+}
+
+#[test]
+fn test_pepper_set_validation() {
+    assert!(PepperSet::new(1, [0u8; 31]).is_err());
+    assert!(PepperSet::new(1, [0u8; 32]).unwrap().with_version(1, [1u8; 32]).is_err());
+
+    let peppers = PepperSet::new(2, [2u8; 32]).unwrap().with_version(1, [1u8; 32]).unwrap();
+    assert_eq!(peppers.current_version(), 2);
+    assert_eq!(format!("{:?}", peppers), "PepperSet { versions: [1, 2] }");
+}
+
+#[test]
+fn test_pepper_set_from_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("peppers");
+    std::fs::write(
+        &path,
+        format!("# rotated 2024-06-01\n1:{}\n\n2:{}\n", "ab".repeat(32), "CD".repeat(32)),
+    )
+    .unwrap();
+
+    let peppers = PepperSet::from_file(&path).unwrap();
+    assert_eq!(peppers.current_version(), 2);
+    assert_eq!(peppers.get(1), Some(&[0xab; 32][..]));
+    assert_eq!(peppers.get(2), Some(&[0xcd; 32][..]));
+
+    std::fs::write(&path, "1:not-hex").unwrap();
+    assert!(PepperSet::from_file(&path).is_err());
+    std::fs::write(&path, "# nothing yet\n").unwrap();
+    assert!(PepperSet::from_file(&path).is_err());
+    assert!(PepperSet::from_file(dir.path().join("missing")).is_err());
+}
+
+#[test]
+fn test_benchmark() {
+    let benchmark = weak_config().benchmark(5).unwrap();
+    assert_eq!(benchmark.config, weak_config());
+    assert!(benchmark.p50 <= benchmark.p99);
+    assert!(benchmark.throughput > 0.0);
+
+    assert!(weak_config().benchmark(0).is_err());
+}
@@ -1,7 +1,6 @@
 use crate::rate_limit::*;
 use crate::storage::{InMemoryStorage, ApiKeyStorage};
-use crate::validation::ApiKeyMetadata;
-use crate::generation::Environment;
+use crate::generation::{generate_api_key, Environment};
 use chrono::Duration;
 use std::sync::atomic::AtomicI64;
 use std::sync::Arc;
@@ -30,22 +29,25 @@ impl TimeProvider for MockTimeProvider {
     }
 }
 
-async fn create_test_storage() -> InMemoryStorage {
+/// Storage holding three freshly generated test keys, returned alongside it
+async fn create_test_storage() -> (InMemoryStorage, [String; 3]) {
     let storage = InMemoryStorage::new();
     
     // Initialize test keys
-    let test_keys = ["test_key", "key1", "key2"];
-    for key in test_keys {
-        let metadata = ApiKeyMetadata::new(Environment::Test, key).unwrap();
-        storage.store_key(key, metadata).await.unwrap();
+    let mut keys: [String; 3] = Default::default();
+    for slot in &mut keys {
+        let (key, metadata) = generate_api_key(Environment::Test).unwrap();
+        storage.store_key(&key, metadata).await.unwrap();
+        *slot = key.expose_secret().to_string();
     }
     
-    storage
+    (storage, keys)
 }
 
 #[tokio::test]
 async fn test_basic_rate_limit() {
-    let storage = Arc::new(create_test_storage().await);
+    let (storage, [test_key, _, _]) = create_test_storage().await;
+    let storage = Arc::new(storage);
     let rate_limit_storage = InMemoryRateLimitStorage::new(storage.clone());
     let time_provider = Arc::new(MockTimeProvider::new(1000));
     let mut limiter = RateLimiter::with_time_provider(rate_limit_storage, time_provider.clone());
@@ -59,21 +61,22 @@ async fn test_basic_rate_limit() {
     limiter.set_config(config);
 
     // First request should succeed
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
 
     // Second request should succeed
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
 
     // Third request should fail
     assert!(matches!(
-        limiter.check_rate_limit("test_key").await,
+        limiter.check_rate_limit(&test_key).await,
         Err(RateLimitError::RateLimitExceeded)
     ));
 }
 
 #[tokio::test]
 async fn test_window_reset() {
-    let storage = Arc::new(create_test_storage().await);
+    let (storage, [test_key, _, _]) = create_test_storage().await;
+    let storage = Arc::new(storage);
     let rate_limit_storage = InMemoryRateLimitStorage::new(storage.clone());
     let time_provider = Arc::new(MockTimeProvider::new(1000));
     let mut limiter = RateLimiter::with_time_provider(rate_limit_storage, time_provider.clone());
@@ -87,10 +90,10 @@ async fn test_window_reset() {
     limiter.set_config(config);
 
     // Use up the rate limit
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
     assert!(matches!(
-        limiter.check_rate_limit("test_key").await,
+        limiter.check_rate_limit(&test_key).await,
         Err(RateLimitError::RateLimitExceeded)
     ));
 
@@ -98,17 +101,18 @@ async fn test_window_reset() {
     time_provider.advance(61);
 
     // Should be able to make requests again
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
     assert!(matches!(
-        limiter.check_rate_limit("test_key").await,
+        limiter.check_rate_limit(&test_key).await,
         Err(RateLimitError::RateLimitExceeded)
     ));
 }
 
 #[tokio::test]
 async fn test_burst_limit() {
-    let storage = Arc::new(create_test_storage().await);
+    let (storage, [test_key, _, _]) = create_test_storage().await;
+    let storage = Arc::new(storage);
     let rate_limit_storage = InMemoryRateLimitStorage::new(storage.clone());
     let time_provider = Arc::new(MockTimeProvider::new(1000));
     let mut limiter = RateLimiter::with_time_provider(rate_limit_storage, time_provider.clone());
@@ -122,14 +126,14 @@ async fn test_burst_limit() {
     limiter.set_config(config);
 
     // First request should succeed (2 tokens -> 1 token)
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
     
     // Second request should succeed (1 token -> 0 tokens)
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
     
     // Third request should fail (0 tokens)
     assert!(matches!(
-        limiter.check_rate_limit("test_key").await,
+        limiter.check_rate_limit(&test_key).await,
         Err(RateLimitError::RateLimitExceeded)
     ));
 
@@ -137,18 +141,19 @@ async fn test_burst_limit() {
     time_provider.advance(1);
 
     // Should be able to make one request (1 token -> 0 tokens)
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
 
     // Should fail again (0 tokens)
     assert!(matches!(
-        limiter.check_rate_limit("test_key").await,
+        limiter.check_rate_limit(&test_key).await,
         Err(RateLimitError::RateLimitExceeded)
     ));
 }
 
 #[tokio::test]
 async fn test_token_refill() {
-    let storage = Arc::new(create_test_storage().await);
+    let (storage, [test_key, _, _]) = create_test_storage().await;
+    let storage = Arc::new(storage);
     let rate_limit_storage = InMemoryRateLimitStorage::new(storage.clone());
     let time_provider = Arc::new(MockTimeProvider::new(1000));
     let mut limiter = RateLimiter::with_time_provider(rate_limit_storage, time_provider.clone());
@@ -162,10 +167,10 @@ async fn test_token_refill() {
     limiter.set_config(config);
 
     // Use up initial tokens
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
     assert!(matches!(
-        limiter.check_rate_limit("test_key").await,
+        limiter.check_rate_limit(&test_key).await,
         Err(RateLimitError::RateLimitExceeded)
     ));
 
@@ -173,16 +178,17 @@ async fn test_token_refill() {
     time_provider.advance(1);
 
     // Should be able to make one more request
-    assert!(limiter.check_rate_limit("test_key").await.is_ok());
+    assert!(limiter.check_rate_limit(&test_key).await.is_ok());
     assert!(matches!(
-        limiter.check_rate_limit("test_key").await,
+        limiter.check_rate_limit(&test_key).await,
         Err(RateLimitError::RateLimitExceeded)
     ));
 }
 
 #[tokio::test]
 async fn test_invalid_key() {
-    let storage = Arc::new(create_test_storage().await);
+    let (storage, _) = create_test_storage().await;
+    let storage = Arc::new(storage);
     let rate_limit_storage = InMemoryRateLimitStorage::new(storage.clone());
     let time_provider = Arc::new(MockTimeProvider::new(1000));
     let limiter = RateLimiter::with_time_provider(rate_limit_storage, time_provider);
@@ -196,7 +202,8 @@ async fn test_invalid_key() {
 
 #[tokio::test]
 async fn test_multiple_keys() {
-    let storage = Arc::new(create_test_storage().await);
+    let (storage, [_, key1, key2]) = create_test_storage().await;
+    let storage = Arc::new(storage);
     let rate_limit_storage = InMemoryRateLimitStorage::new(storage.clone());
     let time_provider = Arc::new(MockTimeProvider::new(1000));
     let mut limiter = RateLimiter::with_time_provider(rate_limit_storage, time_provider);
@@ -210,18 +217,18 @@ async fn test_multiple_keys() {
     limiter.set_config(config);
 
     // Use up rate limit for first key
-    assert!(limiter.check_rate_limit("key1").await.is_ok());
-    assert!(limiter.check_rate_limit("key1").await.is_ok());
+    assert!(limiter.check_rate_limit(&key1).await.is_ok());
+    assert!(limiter.check_rate_limit(&key1).await.is_ok());
     assert!(matches!(
-        limiter.check_rate_limit("key1").await,
+        limiter.check_rate_limit(&key1).await,
         Err(RateLimitError::RateLimitExceeded)
     ));
 
     // Second key should still have full rate limit
-    assert!(limiter.check_rate_limit("key2").await.is_ok());
-    assert!(limiter.check_rate_limit("key2").await.is_ok());
+    assert!(limiter.check_rate_limit(&key2).await.is_ok());
+    assert!(limiter.check_rate_limit(&key2).await.is_ok());
     assert!(matches!(
-        limiter.check_rate_limit("key2").await,
+        limiter.check_rate_limit(&key2).await,
         Err(RateLimitError::RateLimitExceeded)
     ));
 } 
\ No newline at end of file
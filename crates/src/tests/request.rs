@@ -1,15 +1,13 @@
 use crate::request::*;
+use chrono::Utc;
 use std::net::IpAddr;
 use std::str::FromStr;
-use chrono::Utc;
+use std::sync::Arc;
 
 fn create_test_headers() -> Vec<(String, String)> {
     vec![
         ("User-Agent".to_string(), "test-agent".to_string()),
-        (
-            "X-Request-Timestamp".to_string(),
-            Utc::now().to_rfc3339(),
-        ),
+        ("X-Request-Timestamp".to_string(), Utc::now().to_rfc3339()),
         ("X-Request-Id".to_string(), "test-request-id".to_string()),
     ]
 }
@@ -53,10 +51,7 @@ fn test_invalid_ip() {
 
 #[test]
 fn test_old_request() {
-    let validator = RequestValidator::new(
-        chrono::Duration::minutes(5),
-        None,
-    );
+    let validator = RequestValidator::new(chrono::Duration::minutes(5), None);
 
     let metadata = RequestMetadata {
         ip_address: IpAddr::from_str("127.0.0.1").unwrap(),
@@ -91,4 +86,576 @@ fn test_missing_headers() {
         Err(RequestValidationError::MissingHeader(_)) => (),
         _ => panic!("Expected MissingHeader error"),
     }
-} 
\ No newline at end of file
+}
+
+fn signature_validator() -> RequestValidator {
+    RequestValidator::new(chrono::Duration::minutes(5), None)
+}
+
+fn signed<'a>(
+    method: &'a str,
+    path: &'a str,
+    body: &'a [u8],
+    timestamp: &'a str,
+) -> SignedRequest<'a> {
+    SignedRequest {
+        method,
+        path,
+        body,
+        timestamp,
+        request_id: "req-1",
+    }
+}
+
+#[test]
+fn test_valid_signature() {
+    let key = "tronch_sk_test_secret";
+    let timestamp = Utc::now().to_rfc3339();
+    let request = signed("POST", "/api/v1/keys", b"{\"name\":\"ci\"}", &timestamp);
+    let signature = sign_request(&request, key);
+    assert!(signature.starts_with("v1="));
+
+    assert!(signature_validator()
+        .validate_signature(&request, &signature, key)
+        .is_ok());
+}
+
+#[test]
+fn test_tampered_request_rejected() {
+    let key = "tronch_sk_test_secret";
+    let timestamp = Utc::now().to_rfc3339();
+    let request = signed("POST", "/api/v1/keys", b"{}", &timestamp);
+    let signature = sign_request(&request, key);
+    let validator = signature_validator();
+
+    let tampered = [
+        SignedRequest {
+            method: "PUT",
+            ..request
+        },
+        SignedRequest {
+            path: "/api/v1/other",
+            ..request
+        },
+        SignedRequest {
+            body: b"{\"a\":1}",
+            ..request
+        },
+        SignedRequest {
+            timestamp: "2024-01-01T00:00:00Z",
+            ..request
+        },
+        SignedRequest {
+            request_id: "req-2",
+            ..request
+        },
+    ];
+    for tampered in tampered {
+        assert_eq!(
+            validator.validate_signature(&tampered, &signature, key),
+            Err(RequestValidationError::InvalidSignature)
+        );
+    }
+    assert_eq!(
+        validator.validate_signature(&request, &signature, "tronch_sk_test_other"),
+        Err(RequestValidationError::InvalidSignature)
+    );
+}
+
+#[test]
+fn test_multiple_signature_versions() {
+    let key = "tronch_sk_test_secret";
+    let timestamp = Utc::now().to_rfc3339();
+    let request = signed("GET", "/api/v1/keys", b"", &timestamp);
+    let signature = sign_request(&request, key);
+    let validator = signature_validator();
+
+    // Unknown versions and stale signatures alongside a valid one are tolerated
+    let header = format!("v2=deadbeef, v1={}, {}", "00".repeat(32), signature);
+    assert!(validator.validate_signature(&request, &header, key).is_ok());
+
+    // Without a supported version there is nothing to verify
+    assert_eq!(
+        validator.validate_signature(&request, "v2=deadbeef", key),
+        Err(RequestValidationError::InvalidSignature)
+    );
+    assert_eq!(
+        validator.validate_signature(&request, "", key),
+        Err(RequestValidationError::InvalidSignature)
+    );
+}
+
+#[test]
+fn test_signature_entries_capped() {
+    let key = "tronch_sk_test_secret";
+    let timestamp = Utc::now().to_rfc3339();
+    let request = signed("GET", "/api/v1/keys", b"", &timestamp);
+    let signature = sign_request(&request, key);
+    let validator = signature_validator();
+
+    let stale = format!("v1={}", "00".repeat(32));
+    let mut entries = vec![stale.as_str(); MAX_SIGNATURE_ENTRIES - 1];
+    entries.push(&signature);
+    let header = entries.join(",");
+    assert!(validator.validate_signature(&request, &header, key).is_ok());
+
+    // One entry too many is refused, even with a valid signature among them
+    let header = format!("{},{}", stale, header);
+    assert!(matches!(
+        validator.validate_signature(&request, &header, key),
+        Err(RequestValidationError::InvalidHeaderValue(_))
+    ));
+}
+
+#[test]
+fn test_canonical_request() {
+    let canonical = signed("post", "/api/v1/keys", b"", "2024-01-01T00:00:00Z").canonical();
+    assert_eq!(
+        canonical,
+        "POST\n/api/v1/keys\n2024-01-01T00:00:00Z\nreq-1\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
+
+fn request_with_id(request_id: &str) -> RequestMetadata {
+    RequestMetadata {
+        ip_address: IpAddr::from_str("127.0.0.1").unwrap(),
+        user_agent: "test-agent".to_string(),
+        timestamp: Utc::now(),
+        request_id: request_id.to_string(),
+    }
+}
+
+fn test_key() -> crate::validation::ApiKeyMetadata {
+    let (_, key) =
+        crate::generation::generate_api_key(crate::generation::Environment::Test).unwrap();
+    key
+}
+
+#[test]
+fn test_replayed_request() {
+    let validator = RequestValidator::new(chrono::Duration::minutes(5), None);
+    let metadata = request_with_id("req-1");
+
+    assert!(validator.validate_request(&metadata).is_ok());
+    assert_eq!(
+        validator.validate_request(&metadata),
+        Err(RequestValidationError::ReplayedRequest("req-1".to_string()))
+    );
+
+    // Clones share the replay cache
+    assert!(validator.clone().validate_request(&metadata).is_err());
+    assert!(validator
+        .validate_request(&request_with_id("req-2"))
+        .is_ok());
+}
+
+#[test]
+fn test_request_ids_scoped_to_key() {
+    let validator = RequestValidator::new(chrono::Duration::minutes(5), None);
+    let metadata = request_with_id("req-1");
+    let (first, second) = (test_key(), test_key());
+
+    assert!(validator
+        .validate_request_for_key(&metadata, &first)
+        .is_ok());
+    assert!(validator
+        .validate_request_for_key(&metadata, &second)
+        .is_ok());
+    assert!(validator.validate_request(&metadata).is_ok());
+    assert_eq!(
+        validator.validate_request_for_key(&metadata, &first),
+        Err(RequestValidationError::ReplayedRequest("req-1".to_string()))
+    );
+}
+
+#[test]
+fn test_signed_request() {
+    let validator = RequestValidator::new(
+        chrono::Duration::minutes(5),
+        Some(vec![IpAddr::from_str("127.0.0.1").unwrap()]),
+    );
+    let key = test_key();
+    let api_key = "tronch_sk_test_secret";
+    let metadata = request_with_id("req-1");
+    let timestamp = metadata.timestamp.to_rfc3339();
+    let request = signed("GET", "/api/v1/keys", b"", &timestamp);
+    let signature = sign_request(&request, api_key);
+
+    assert!(validator
+        .validate_signed_request(&metadata, &key, &request, &signature, api_key)
+        .is_ok());
+    assert_eq!(
+        validator.validate_signed_request(&metadata, &key, &request, &signature, api_key),
+        Err(RequestValidationError::ReplayedRequest("req-1".to_string()))
+    );
+
+    // The signature has to cover the id being recorded
+    let other = request_with_id("req-2");
+    assert_eq!(
+        validator.validate_signed_request(&other, &key, &request, &signature, api_key),
+        Err(RequestValidationError::InvalidSignature)
+    );
+}
+
+#[test]
+fn test_rejected_request_not_remembered() {
+    let validator = RequestValidator::new(
+        chrono::Duration::minutes(5),
+        Some(vec![IpAddr::from_str("127.0.0.1").unwrap()]),
+    );
+    let key = test_key();
+    let api_key = "tronch_sk_test_secret";
+    let metadata = request_with_id("req-1");
+    let timestamp = metadata.timestamp.to_rfc3339();
+    let request = signed("GET", "/api/v1/keys", b"", &timestamp);
+
+    // Passes the age and IP checks but fails the signature
+    let forged = format!("v1={}", "00".repeat(32));
+    assert_eq!(
+        validator.validate_signed_request(&metadata, &key, &request, &forged, api_key),
+        Err(RequestValidationError::InvalidSignature)
+    );
+
+    let signature = sign_request(&request, api_key);
+    assert!(validator
+        .validate_signed_request(&metadata, &key, &request, &signature, api_key)
+        .is_ok());
+}
+
+#[test]
+fn test_replay_store_expiry() {
+    let store = InMemoryReplayStore::new();
+    let now = Utc::now();
+
+    assert!(store.check_and_insert("", "req-1", now + chrono::Duration::minutes(5)));
+    assert!(!store.check_and_insert("", "req-1", now + chrono::Duration::minutes(5)));
+    assert!(store.check_and_insert("key", "req-1", now + chrono::Duration::minutes(5)));
+
+    // An id whose window has passed may be reused and is swept by purge
+    assert!(store.check_and_insert("", "req-2", now - chrono::Duration::seconds(1)));
+    assert_eq!(store.len(), 3);
+    store.purge_expired();
+    assert_eq!(store.len(), 2);
+    assert!(store.check_and_insert("", "req-2", now + chrono::Duration::minutes(5)));
+}
+
+#[test]
+fn test_custom_replay_store() {
+    let store = Arc::new(InMemoryReplayStore::new());
+    let validator =
+        RequestValidator::new(chrono::Duration::minutes(5), None).with_replay_store(store.clone());
+
+    assert!(validator
+        .validate_request(&request_with_id("req-1"))
+        .is_ok());
+    assert_eq!(store.len(), 1);
+}
+
+fn net(s: &str) -> ipnet::IpNet {
+    s.parse().unwrap()
+}
+
+fn request_from(ip: &str) -> RequestMetadata {
+    RequestMetadata {
+        ip_address: IpAddr::from_str(ip).unwrap(),
+        user_agent: "test-agent".to_string(),
+        timestamp: Utc::now(),
+        request_id: format!("req-{}", ip),
+    }
+}
+
+#[test]
+fn test_ip_policy_cidr() {
+    let policy = IpPolicy::allow(vec![net("10.1.0.0/16"), net("2001:db8::/32")]);
+    assert!(policy.is_allowed(IpAddr::from_str("10.1.42.7").unwrap()));
+    assert!(policy.is_allowed(IpAddr::from_str("2001:db8::1").unwrap()));
+    assert!(policy.is_allowed(IpAddr::from_str("::ffff:10.1.0.1").unwrap()));
+    assert!(!policy.is_allowed(IpAddr::from_str("10.2.0.1").unwrap()));
+    assert!(!policy.is_allowed(IpAddr::from_str("2001:db9::1").unwrap()));
+}
+
+#[test]
+fn test_ip_policy_deny_takes_precedence() {
+    let policy = IpPolicy::allow(vec![net("10.0.0.0/8")]).with_deny(vec![net("10.0.13.0/24")]);
+    assert!(policy.is_allowed(IpAddr::from_str("10.0.12.1").unwrap()));
+    assert!(!policy.is_allowed(IpAddr::from_str("10.0.13.1").unwrap()));
+
+    // A deny list on its own leaves everything else open
+    let policy = IpPolicy::default().with_deny(vec![net("192.168.0.0/16")]);
+    assert!(policy.is_allowed(IpAddr::from_str("8.8.8.8").unwrap()));
+    assert!(!policy.is_allowed(IpAddr::from_str("192.168.1.1").unwrap()));
+}
+
+#[test]
+fn test_validator_ip_policy() {
+    let validator = RequestValidator::new(chrono::Duration::minutes(5), None)
+        .with_ip_policy(IpPolicy::allow(vec![net("172.16.0.0/12")]));
+
+    assert!(validator
+        .validate_request(&request_from("172.20.1.1"))
+        .is_ok());
+    assert!(matches!(
+        validator.validate_request(&request_from("172.32.0.1")),
+        Err(RequestValidationError::IpNotAllowed(_))
+    ));
+}
+
+#[test]
+fn test_per_key_ip_policy() {
+    let validator = RequestValidator::new(chrono::Duration::minutes(5), None)
+        .with_ip_policy(IpPolicy::default().with_deny(vec![net("10.9.9.9/32")]));
+    let mut key = test_key();
+    key.ip_policy = IpPolicy::allow(vec![net("10.0.0.0/8")]);
+
+    assert!(validator
+        .validate_request_for_key(&request_from("10.1.1.1"), &key)
+        .is_ok());
+    assert!(matches!(
+        validator.validate_request_for_key(&request_from("192.168.1.1"), &key),
+        Err(RequestValidationError::IpNotAllowed(_))
+    ));
+    // The validator's deny list applies on top of the key's allowlist
+    assert!(matches!(
+        validator.validate_request_for_key(&request_from("10.9.9.9"), &key),
+        Err(RequestValidationError::IpNotAllowed(_))
+    ));
+    // Keys without a policy only get the validator's rules
+    assert!(validator
+        .validate_request(&request_from("192.168.1.1"))
+        .is_ok());
+}
+
+fn proxied_by(header: ForwardedHeader) -> RequestValidator {
+    RequestValidator::new(chrono::Duration::minutes(5), None)
+        .with_trusted_proxies(vec![net("10.0.0.0/8"), net("fd00::/8")], header)
+}
+
+fn proxied_validator() -> RequestValidator {
+    proxied_by(ForwardedHeader::XForwardedFor)
+}
+
+fn header(name: &str, value: &str) -> (String, String) {
+    (name.to_string(), value.to_string())
+}
+
+fn ip(s: &str) -> IpAddr {
+    IpAddr::from_str(s).unwrap()
+}
+
+#[test]
+fn test_untrusted_peer_headers_ignored() {
+    let headers = vec![header("X-Forwarded-For", "1.2.3.4")];
+    let client = proxied_validator()
+        .client_ip(&headers, ip("203.0.113.9"))
+        .unwrap();
+    assert_eq!(client, ip("203.0.113.9"));
+}
+
+#[test]
+fn test_x_forwarded_for_right_to_left() {
+    let validator = proxied_validator();
+
+    // The client prepended a spoofed address; the first untrusted hop from the right wins
+    let headers = vec![header("X-Forwarded-For", "6.6.6.6, 198.51.100.7, 10.0.0.2")];
+    assert_eq!(
+        validator.client_ip(&headers, ip("10.0.0.1")).unwrap(),
+        ip("198.51.100.7")
+    );
+
+    // Repeated headers form one chain
+    let headers = vec![
+        header("X-Forwarded-For", "198.51.100.7"),
+        header("x-forwarded-for", "10.0.0.2:8080"),
+    ];
+    assert_eq!(
+        validator.client_ip(&headers, ip("10.0.0.1")).unwrap(),
+        ip("198.51.100.7")
+    );
+
+    // With every hop trusted, the leftmost address is the client
+    let headers = vec![header("X-Forwarded-For", "10.1.1.1, 10.0.0.2")];
+    assert_eq!(
+        validator.client_ip(&headers, ip("10.0.0.1")).unwrap(),
+        ip("10.1.1.1")
+    );
+}
+
+#[test]
+fn test_forwarded_header() {
+    let validator = proxied_by(ForwardedHeader::Forwarded);
+    let headers = vec![
+        header(
+            "Forwarded",
+            "for=6.6.6.6, for=\"[2001:db8:cafe::17]:4711\";proto=https",
+        ),
+        header("Forwarded", "for=\"[fd00::2]\";by=10.0.0.1"),
+        // Only the configured header is read
+        header("X-Forwarded-For", "192.0.2.1"),
+    ];
+    assert_eq!(
+        validator.client_ip(&headers, ip("10.0.0.1")).unwrap(),
+        ip("2001:db8:cafe::17")
+    );
+
+    let headers = vec![header("Forwarded", "For=192.0.2.60:443;proto=http")];
+    assert_eq!(
+        validator.client_ip(&headers, ip("10.0.0.1")).unwrap(),
+        ip("192.0.2.60")
+    );
+}
+
+#[test]
+fn test_unconfigured_headers_ignored() {
+    // A client can send Forwarded through a proxy that only appends X-Forwarded-For
+    let headers = vec![
+        header("Forwarded", "for=10.1.1.1"),
+        header("X-Real-IP", "10.1.1.1"),
+        header("X-Forwarded-For", "198.51.100.7"),
+    ];
+    assert_eq!(
+        proxied_validator()
+            .client_ip(&headers, ip("10.0.0.1"))
+            .unwrap(),
+        ip("198.51.100.7")
+    );
+
+    let headers = vec![header("Forwarded", "for=198.51.100.7")];
+    assert_eq!(
+        proxied_validator()
+            .client_ip(&headers, ip("10.0.0.1"))
+            .unwrap(),
+        ip("10.0.0.1")
+    );
+}
+
+#[test]
+fn test_hidden_forwarded_nodes() {
+    let validator = proxied_by(ForwardedHeader::Forwarded);
+
+    // The walk stops at a hop that hides its address, before any spoofable entries
+    for hidden in [
+        "unknown",
+        "\"_gazonk\"",
+        "\"_hidden:_port\"",
+        "\"unknown:80\"",
+    ] {
+        let headers = vec![header(
+            "Forwarded",
+            &format!("for=6.6.6.6, for={}, for=10.0.0.2", hidden),
+        )];
+        assert_eq!(
+            validator.client_ip(&headers, ip("10.0.0.1")).unwrap(),
+            ip("10.0.0.2")
+        );
+    }
+
+    // Untrusted addresses right of a hidden node still win
+    let headers = vec![header("Forwarded", "for=_gazonk, for=198.51.100.7")];
+    assert_eq!(
+        validator.client_ip(&headers, ip("10.0.0.1")).unwrap(),
+        ip("198.51.100.7")
+    );
+
+    let headers = vec![header("X-Forwarded-For", "unknown")];
+    assert_eq!(
+        proxied_validator()
+            .client_ip(&headers, ip("10.0.0.1"))
+            .unwrap(),
+        ip("10.0.0.1")
+    );
+}
+
+#[test]
+fn test_x_real_ip() {
+    let validator = proxied_by(ForwardedHeader::XRealIp);
+    let headers = vec![header("X-Real-IP", "198.51.100.23")];
+    let client = validator.client_ip(&headers, ip("10.0.0.1")).unwrap();
+    assert_eq!(client, ip("198.51.100.23"));
+
+    // Without forwarding headers the proxy itself is the client
+    let client = validator.client_ip(&[], ip("10.0.0.1")).unwrap();
+    assert_eq!(client, ip("10.0.0.1"));
+}
+
+#[test]
+fn test_invalid_forwarded_address() {
+    let headers = vec![header("X-Forwarded-For", "not-an-ip, 10.0.0.2")];
+    assert!(matches!(
+        proxied_validator().client_ip(&headers, ip("10.0.0.1")),
+        Err(RequestValidationError::InvalidHeaderValue(_))
+    ));
+
+    // Junk left of the client is never looked at
+    let headers = vec![header(
+        "X-Forwarded-For",
+        "not-an-ip, 198.51.100.7, 10.0.0.2",
+    )];
+    let client = proxied_validator()
+        .client_ip(&headers, ip("10.0.0.1"))
+        .unwrap();
+    assert_eq!(client, ip("198.51.100.7"));
+}
+
+#[test]
+fn test_extract_client_metadata() {
+    let mut headers = create_test_headers();
+    headers.push(header("X-Forwarded-For", "198.51.100.7"));
+
+    let metadata = proxied_validator()
+        .extract_client_metadata(&headers, ip("10.0.0.1"))
+        .unwrap();
+    assert_eq!(metadata.ip_address, ip("198.51.100.7"));
+}
+
+fn request_at(timestamp: chrono::DateTime<Utc>) -> RequestMetadata {
+    RequestMetadata {
+        timestamp,
+        ..request_with_id(&format!("req-{}", timestamp.timestamp_nanos_opt().unwrap()))
+    }
+}
+
+#[test]
+fn test_future_request() {
+    let validator = RequestValidator::new(chrono::Duration::minutes(5), None)
+        .with_clock_skew(chrono::Duration::seconds(30));
+
+    // A pre-signed request far in the future must not pass as "zero age"
+    let future = Utc::now() + chrono::Duration::hours(1);
+    assert_eq!(
+        validator.validate_request(&request_at(future)),
+        Err(RequestValidationError::RequestFromFuture(future))
+    );
+
+    // Slightly fast client clocks are tolerated
+    let ahead = Utc::now() + chrono::Duration::seconds(10);
+    assert!(validator.validate_request(&request_at(ahead)).is_ok());
+}
+
+#[test]
+fn test_clock_skew_extends_max_age() {
+    let validator = RequestValidator::new(chrono::Duration::minutes(5), None)
+        .with_clock_skew(chrono::Duration::seconds(30));
+
+    let slightly_late = Utc::now() - chrono::Duration::seconds(5 * 60 + 10);
+    assert!(validator
+        .validate_request(&request_at(slightly_late))
+        .is_ok());
+
+    let too_late = Utc::now() - chrono::Duration::seconds(5 * 60 + 40);
+    assert!(matches!(
+        validator.validate_request(&request_at(too_late)),
+        Err(RequestValidationError::RequestTooOld(_))
+    ));
+}
+
+#[test]
+fn test_zero_clock_skew() {
+    let validator = RequestValidator::new(chrono::Duration::minutes(5), None)
+        .with_clock_skew(chrono::Duration::zero());
+
+    let ahead = Utc::now() + chrono::Duration::seconds(10);
+    assert!(matches!(
+        validator.validate_request(&request_at(ahead)),
+        Err(RequestValidationError::RequestFromFuture(_))
+    ));
+}
@@ -1,31 +1,32 @@
+use crate::authorization::Scope;
 use crate::rotation::*;
-use crate::generation::Environment;
-use crate::validation::ApiKeyMetadata;
+use crate::generation::{generate_api_key, generate_key_pair, Environment, KeyFormat, KeyKind, ParsedApiKey};
+use crate::request::IpPolicy;
 use crate::storage::{InMemoryStorage, ApiKeyStorage};
 use chrono::Duration;
 
-async fn create_test_storage() -> InMemoryStorage {
+async fn create_test_storage() -> (InMemoryStorage, ParsedApiKey) {
     let storage = InMemoryStorage::new();
-    let metadata = ApiKeyMetadata::new(Environment::Test, "test_key").unwrap();
-    storage.store_key("test_key", metadata).await.unwrap();
-    storage
+    let (key, metadata) = generate_api_key(Environment::Test).unwrap();
+    storage.store_key(&key, metadata).await.unwrap();
+    (storage, key)
 }
 
 #[tokio::test]
 async fn test_key_rotation() {
-    let storage = create_test_storage().await;
-    let old_key = "test_key";
+    let (storage, old_key) = create_test_storage().await;
     
     let config = RotationConfig {
         grace_period: Duration::hours(24),
         auto_revoke: false,
+        ..Default::default()
     };
 
-    let new_key = rotate_key(&storage, old_key, config).await.unwrap();
-    assert!(new_key.starts_with("tronch_sk_test_"));
+    let new_key = rotate_key(&storage, &old_key, config).await.unwrap();
+    assert!(new_key.expose_secret().starts_with("tronch_sk_test_"));
 
     // Old key should still work during grace period
-    let old_metadata = storage.get_metadata(old_key).await.unwrap();
+    let old_metadata = storage.get_metadata(&old_key).await.unwrap();
     assert!(old_metadata.is_active);
     assert!(!old_metadata.is_revoked);
 
@@ -37,29 +38,110 @@ async fn test_key_rotation() {
 
 #[tokio::test]
 async fn test_rotate_nonexistent_key() {
-    let storage = create_test_storage().await;
+    let (storage, _) = create_test_storage().await;
+    let (unknown, _) = generate_api_key(Environment::Test).unwrap();
     let config = RotationConfig {
         grace_period: Duration::hours(24),
         auto_revoke: false,
+        ..Default::default()
     };
 
-    let result = rotate_key(&storage, "nonexistent", config).await;
+    let result = rotate_key(&storage, &unknown, config).await;
     assert!(matches!(result, Err(KeyRotationError::KeyNotFound)));
 }
 
 #[tokio::test]
 async fn test_rotate_revoked_key() {
     let storage = InMemoryStorage::new();
-    let key = "test_key";
-    let mut metadata = ApiKeyMetadata::new(Environment::Test, key).unwrap();
+    let (key, mut metadata) = generate_api_key(Environment::Test).unwrap();
     metadata.is_revoked = true;
-    storage.store_key(key, metadata).await.unwrap();
+    storage.store_key(&key, metadata).await.unwrap();
 
     let config = RotationConfig {
         grace_period: Duration::hours(24),
         auto_revoke: false,
+        ..Default::default()
     };
 
-    let result = rotate_key(&storage, key, config).await;
+    let result = rotate_key(&storage, &key, config).await;
     assert!(matches!(result, Err(KeyRotationError::KeyRevoked)));
-} 
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_rotation_preserves_ip_policy() {
+    let storage = InMemoryStorage::new();
+    let (key, mut metadata) = generate_api_key(Environment::Test).unwrap();
+    metadata.ip_policy = IpPolicy::allow(vec!["10.0.0.0/8".parse().unwrap()]);
+    storage.store_key(&key, metadata.clone()).await.unwrap();
+
+    let new_key = rotate_key(&storage, &key, RotationConfig::default()).await.unwrap();
+    let new_metadata = storage.get_metadata(&new_key).await.unwrap();
+    assert_eq!(new_metadata.ip_policy, metadata.ip_policy);
+}
+
+#[tokio::test]
+async fn test_rotation_preserves_scopes() {
+    let storage = InMemoryStorage::new();
+    let (key, mut metadata) = generate_api_key(Environment::Test).unwrap();
+    metadata.scopes = vec![Scope::KeysRead];
+    storage.store_key(&key, metadata).await.unwrap();
+
+    let new_key = rotate_key(&storage, &key, RotationConfig::default()).await.unwrap();
+    let new_metadata = storage.get_metadata(&new_key).await.unwrap();
+    assert_eq!(new_metadata.scopes, vec![Scope::KeysRead]);
+}
+
+#[tokio::test]
+async fn test_rotation_preserves_publishable_kind() {
+    let storage = InMemoryStorage::new();
+    let pair = generate_key_pair(Environment::Live).unwrap();
+    storage.store_key(&pair.secret_key, pair.secret_metadata.clone()).await.unwrap();
+    storage.store_key(&pair.publishable_key, pair.publishable_metadata).await.unwrap();
+
+    let new_key = rotate_key(&storage, &pair.publishable_key, RotationConfig::default()).await.unwrap();
+    assert!(new_key.expose_secret().starts_with("tronch_pk_live_"));
+    let new_metadata = storage.get_metadata(&new_key).await.unwrap();
+    assert_eq!(new_metadata.kind, KeyKind::Publishable);
+    assert_eq!(new_metadata.scopes, vec![Scope::PublicRead]);
+    assert_eq!(new_metadata.paired_key, Some(pair.secret_metadata.lookup_id));
+
+    // The secret key now points at the replacement
+    let secret_metadata = storage.get_metadata(&pair.secret_key).await.unwrap();
+    assert_eq!(secret_metadata.paired_key.as_deref(), Some(new_key.lookup_id()));
+}
+
+#[tokio::test]
+async fn test_rotation_without_stored_partner() {
+    let storage = InMemoryStorage::new();
+    let pair = generate_key_pair(Environment::Live).unwrap();
+    storage.store_key(&pair.secret_key, pair.secret_metadata).await.unwrap();
+
+    let new_key = rotate_key(&storage, &pair.secret_key, RotationConfig::default()).await.unwrap();
+    assert!(storage.get_metadata(&new_key).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_rotation_uses_configured_format() {
+    let (storage, key) = create_test_storage().await;
+    let config = RotationConfig {
+        format: KeyFormat::new("acme").with_length(56),
+        ..Default::default()
+    };
+
+    let new_key = rotate_key(&storage, &key, config).await.unwrap();
+    assert!(new_key.expose_secret().starts_with("acme_sk_test_"));
+    assert_eq!(new_key.expose_secret().len(), 56);
+    assert!(storage.get_metadata(&new_key).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_rotation_preserves_custom_environment() {
+    let storage = InMemoryStorage::new();
+    let region = Environment::register("us-east").unwrap();
+    let (key, metadata) = generate_api_key(region).unwrap();
+    storage.store_key(&key, metadata).await.unwrap();
+
+    let new_key = rotate_key(&storage, &key, RotationConfig::default()).await.unwrap();
+    assert!(new_key.expose_secret().starts_with("tronch_sk_us-east_"));
+    assert_eq!(storage.get_metadata(&new_key).await.unwrap().environment, region);
+}
@@ -0,0 +1,44 @@
+use crate::generation::{generate_api_key, generate_key_pair, Environment};
+use crate::secret::SecretApiKey;
+
+#[test]
+fn test_generated_key_is_redacted() {
+    let (key, metadata) = generate_api_key(Environment::Live).unwrap();
+    let plaintext = key.expose_secret();
+    let suffix = &plaintext[plaintext.len() - 4..];
+    let expected = format!("tronch_sk_live_****{}", suffix);
+
+    assert_eq!(key.to_string(), expected);
+    assert_eq!(format!("{:?}", key), expected);
+    assert!(!format!("{:?}", (&key, &metadata)).contains(&plaintext[15..40]));
+}
+
+#[test]
+fn test_short_keys_show_no_suffix() {
+    assert_eq!(SecretApiKey::from("test_key".to_string()).to_string(), "test_****");
+    assert_eq!(SecretApiKey::from("abcdefgh".to_string()).to_string(), "****");
+}
+
+#[test]
+fn test_key_pair_debug_is_redacted() {
+    let pair = generate_key_pair(Environment::Test).unwrap();
+    let debug = format!("{:?}", pair);
+    assert!(!debug.contains(pair.secret_key.expose_secret()));
+    assert!(!debug.contains(pair.publishable_key.expose_secret()));
+}
+
+#[test]
+fn test_metadata_debug_hides_hash() {
+    let (_, metadata) = generate_api_key(Environment::Test).unwrap();
+    let debug = format!("{:?}", metadata);
+    assert!(!debug.contains(&metadata.key_hash));
+    assert!(debug.contains("key_hash: \"<redacted>\""));
+    assert!(debug.contains(&metadata.lookup_id));
+}
+
+#[test]
+fn test_equality() {
+    let key = SecretApiKey::from("tronch_sk_test_abc".to_string());
+    assert_eq!(key, key.clone());
+    assert_ne!(key, SecretApiKey::from("tronch_sk_test_abd".to_string()));
+}
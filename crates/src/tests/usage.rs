@@ -0,0 +1,178 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::Utc;
+use tokio::time::sleep;
+use crate::error::ApiKeyError;
+use crate::generation::{generate_api_key, Environment, ParsedApiKey};
+use crate::storage::{ApiKeyStorage, InMemoryStorage, StorageError};
+use crate::usage::*;
+use crate::validation::ApiKeyMetadata;
+
+/// Counts usage writes on the way to in-memory storage, failing them on request
+#[derive(Debug, Default)]
+struct CountingStorage {
+    inner: InMemoryStorage,
+    usage_writes: AtomicUsize,
+    fail_writes: AtomicBool,
+}
+
+#[async_trait::async_trait]
+impl ApiKeyStorage for CountingStorage {
+    async fn store_key(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
+        self.inner.store_key(key, metadata).await
+    }
+
+    async fn get_metadata(&self, key: &ParsedApiKey) -> Result<ApiKeyMetadata, StorageError> {
+        self.inner.get_metadata(key).await
+    }
+
+    async fn update_metadata(&self, key: &ParsedApiKey, metadata: ApiKeyMetadata) -> Result<(), StorageError> {
+        self.inner.update_metadata(key, metadata).await
+    }
+
+    async fn delete_key(&self, key: &ParsedApiKey) -> Result<(), StorageError> {
+        self.inner.delete_key(key).await
+    }
+
+    async fn list_keys(&self, environment: Environment) -> Result<Vec<String>, StorageError> {
+        self.inner.list_keys(environment).await
+    }
+
+    async fn record_usage(&self, usage: &[KeyUsage]) -> Result<(), StorageError> {
+        if self.fail_writes.load(Ordering::SeqCst) {
+            return Err(StorageError::StorageError("unavailable".to_string()));
+        }
+        self.usage_writes.fetch_add(1, Ordering::SeqCst);
+        self.inner.record_usage(usage).await
+    }
+
+    async fn set_paired_key(&self, lookup_id: &str, paired_key: Option<&str>) -> Result<(), StorageError> {
+        self.inner.set_paired_key(lookup_id, paired_key).await
+    }
+}
+
+/// A tracker over counting storage holding one stored key, with the key and its lookup id
+async fn tracker(flush_interval: Duration) -> (Arc<CountingStorage>, UsageTracker, ParsedApiKey, String) {
+    let storage = Arc::new(CountingStorage::default());
+    let (key, metadata) = generate_api_key(Environment::Test).unwrap();
+    storage.store_key(&key, metadata.clone()).await.unwrap();
+    let tracker = UsageTracker::new(storage.clone(), flush_interval);
+    (storage, tracker, key, metadata.lookup_id)
+}
+
+#[tokio::test]
+async fn test_usage_is_coalesced_until_flush() {
+    let (storage, tracker, key, lookup_id) = tracker(Duration::from_secs(60)).await;
+    let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+    for _ in 0..100 {
+        tracker.record(&lookup_id, None);
+    }
+    tracker.record(&lookup_id, Some(ip));
+    tracker.record("other", None);
+    assert_eq!(tracker.pending(), 2);
+    assert_eq!(storage.usage_writes.load(Ordering::SeqCst), 0);
+
+    // One write covers every buffered use
+    assert_eq!(tracker.flush().await.unwrap(), 2);
+    assert_eq!(storage.usage_writes.load(Ordering::SeqCst), 1);
+    assert_eq!(tracker.pending(), 0);
+
+    let metadata = storage.get_metadata(&key).await.unwrap();
+    assert_eq!(metadata.request_count, 101);
+    assert_eq!(metadata.last_used_ip, Some(ip));
+    assert!(metadata.last_used_at.is_some());
+
+    // Nothing buffered, nothing written
+    assert_eq!(tracker.flush().await.unwrap(), 0);
+    assert_eq!(storage.usage_writes.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_usage_keeps_latest_use() {
+    let (storage, tracker, key, lookup_id) = tracker(Duration::from_secs(60)).await;
+    let earlier: IpAddr = "192.0.2.1".parse().unwrap();
+    let later: IpAddr = "192.0.2.2".parse().unwrap();
+    let now = Utc::now();
+
+    tracker.record_at(&lookup_id, now, Some(later));
+    tracker.record_at(&lookup_id, now - chrono::Duration::minutes(5), Some(earlier));
+    tracker.record_at(&lookup_id, now - chrono::Duration::minutes(1), None);
+    tracker.flush().await.unwrap();
+
+    let metadata = storage.get_metadata(&key).await.unwrap();
+    assert_eq!(metadata.last_used_at, Some(now));
+    assert_eq!(metadata.last_used_ip, Some(later));
+    assert_eq!(metadata.request_count, 3);
+}
+
+#[tokio::test]
+async fn test_failed_flush_keeps_usage() {
+    let (storage, tracker, key, lookup_id) = tracker(Duration::from_secs(60)).await;
+
+    tracker.record(&lookup_id, None);
+    storage.fail_writes.store(true, Ordering::SeqCst);
+    assert!(tracker.flush().await.is_err());
+    assert_eq!(tracker.pending(), 1);
+
+    // Uses recorded meanwhile join the retried batch
+    tracker.record(&lookup_id, None);
+    storage.fail_writes.store(false, Ordering::SeqCst);
+    assert_eq!(tracker.flush().await.unwrap(), 1);
+    assert_eq!(storage.get_metadata(&key).await.unwrap().request_count, 2);
+}
+
+#[tokio::test]
+async fn test_periodic_flush() {
+    let (storage, tracker, key, lookup_id) = tracker(Duration::from_millis(20)).await;
+    tracker.start_periodic_flush();
+
+    tracker.record(&lookup_id, None);
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(tracker.pending(), 0);
+    assert_eq!(storage.get_metadata(&key).await.unwrap().request_count, 1);
+
+    // Stopping flushes what is left
+    tracker.record(&lookup_id, None);
+    tracker.stop().await.unwrap();
+    assert_eq!(tracker.pending(), 0);
+    assert_eq!(storage.get_metadata(&key).await.unwrap().request_count, 2);
+}
+
+#[tokio::test]
+async fn test_stop_waits_for_periodic_flush() {
+    let (storage, tracker, key, lookup_id) = tracker(Duration::from_secs(60)).await;
+    tracker.start_periodic_flush();
+    tracker.start_periodic_flush();
+
+    // Stopping doesn't wait out the flush interval
+    tracker.record(&lookup_id, None);
+    let stopped = tokio::time::timeout(Duration::from_secs(5), tracker.stop()).await;
+    assert_eq!(stopped.unwrap().unwrap(), 1);
+    assert_eq!(storage.get_metadata(&key).await.unwrap().request_count, 1);
+}
+
+#[tokio::test]
+async fn test_tracker_authenticate_buffers_use() {
+    let (storage, tracker, key, lookup_id) = tracker(Duration::from_secs(60)).await;
+    let ip: IpAddr = "198.51.100.4".parse().unwrap();
+
+    let authenticated = tracker.authenticate(key.expose_secret(), Some(ip)).await.unwrap();
+    assert_eq!(authenticated.lookup_id, lookup_id);
+    assert_eq!(authenticated.metadata.request_count, 1);
+    assert_eq!(authenticated.metadata.last_used_ip, Some(ip));
+
+    // Storage hears about the use only on flush
+    assert_eq!(storage.usage_writes.load(Ordering::SeqCst), 0);
+    assert_eq!(storage.get_metadata(&key).await.unwrap().last_used_at, None);
+    tracker.flush().await.unwrap();
+    let metadata = storage.get_metadata(&key).await.unwrap();
+    assert_eq!(metadata.last_used_at, authenticated.metadata.last_used_at);
+    assert_eq!(metadata.last_used_ip, Some(ip));
+
+    // Rejected keys aren't recorded
+    assert_eq!(tracker.authenticate("not a key", Some(ip)).await.unwrap_err(), ApiKeyError::InvalidFormat);
+    assert_eq!(tracker.pending(), 0);
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::mem;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use crate::authentication::{authenticate_parsed, AuthenticatedKey};
+use crate::error::{ApiKeyError, Result as AuthResult};
+use crate::generation::{KeyFormat, ParsedApiKey};
+use crate::storage::{apply_usage, ApiKeyStorage, StorageError};
+
+/// Uses of one key, coalesced since they were last written to storage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub lookup_id: String,
+    /// Time of the latest use
+    pub last_used_at: DateTime<Utc>,
+    /// Client address of the latest use that had one
+    pub last_used_ip: Option<IpAddr>,
+    /// Number of uses, added to the stored request count
+    pub requests: u64,
+}
+
+impl KeyUsage {
+    /// A single use of the key with `lookup_id`
+    pub fn new(lookup_id: impl Into<String>, used_at: DateTime<Utc>, ip: Option<IpAddr>) -> Self {
+        Self {
+            lookup_id: lookup_id.into(),
+            last_used_at: used_at,
+            last_used_ip: ip,
+            requests: 1,
+        }
+    }
+
+    /// Folds `other`, a batch of uses of the same key, into this one
+    fn merge(&mut self, other: KeyUsage) {
+        if other.last_used_at >= self.last_used_at {
+            self.last_used_at = other.last_used_at;
+            self.last_used_ip = other.last_used_ip.or(self.last_used_ip);
+        } else {
+            self.last_used_ip = self.last_used_ip.or(other.last_used_ip);
+        }
+        self.requests += other.requests;
+    }
+}
+
+/// Buffers key usage in memory and writes it to storage in batches
+///
+/// Recording a use only touches the in-memory buffer, where repeated uses
+/// of a key collapse into one [`KeyUsage`]. [`UsageTracker::flush`] writes
+/// the buffer with a single [`ApiKeyStorage::record_usage`] call, so storage
+/// sees at most one write per key per flush however busy the key is.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), tronch::StorageError> {
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use tronch::{ApiKeyStorage, InMemoryStorage, UsageTracker};
+///
+/// let storage: Arc<dyn ApiKeyStorage> = Arc::new(InMemoryStorage::new());
+/// let tracker = UsageTracker::new(storage, Duration::from_secs(30));
+/// tracker.start_periodic_flush();
+///
+/// tracker.record("lookup-id", None);
+/// tracker.record("lookup-id", "10.0.0.1".parse().ok());
+/// assert_eq!(tracker.pending(), 1);
+///
+/// tracker.stop().await?;
+/// assert_eq!(tracker.pending(), 0);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct UsageTracker {
+    storage: Arc<dyn ApiKeyStorage>,
+    pending: Arc<Mutex<HashMap<String, KeyUsage>>>,
+    flush_interval: Duration,
+    stopped: watch::Sender<bool>,
+    flush_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl UsageTracker {
+    pub fn new(storage: Arc<dyn ApiKeyStorage>, flush_interval: Duration) -> Self {
+        Self {
+            storage,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            flush_interval,
+            stopped: watch::Sender::new(false),
+            flush_task: Mutex::new(None),
+        }
+    }
+
+    /// The storage usage is flushed to
+    pub fn storage(&self) -> &Arc<dyn ApiKeyStorage> {
+        &self.storage
+    }
+
+    /// Records a use of the key with `lookup_id` now, from `ip` if known
+    pub fn record(&self, lookup_id: &str, ip: Option<IpAddr>) {
+        self.record_at(lookup_id, Utc::now(), ip);
+    }
+
+    /// Records a use of the key with `lookup_id` at `used_at`
+    pub fn record_at(&self, lookup_id: &str, used_at: DateTime<Utc>, ip: Option<IpAddr>) {
+        merge_into(&mut self.pending.lock().unwrap(), KeyUsage::new(lookup_id, used_at, ip));
+    }
+
+    /// Number of keys with usage waiting to be flushed
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Writes buffered usage to storage, returning how many keys it covered
+    ///
+    /// If the write fails the usage goes back into the buffer for the next flush.
+    pub async fn flush(&self) -> Result<usize, StorageError> {
+        flush(&*self.storage, &self.pending).await
+    }
+
+    /// Flushes every `flush_interval` until [`UsageTracker::stop`] is called
+    ///
+    /// A failed flush keeps its usage buffered and is retried on the next tick.
+    /// Does nothing if periodic flushing has already been started.
+    pub fn start_periodic_flush(&self) {
+        let mut flush_task = self.flush_task.lock().unwrap();
+        if flush_task.is_some() {
+            return;
+        }
+
+        let storage = self.storage.clone();
+        let pending = self.pending.clone();
+        let flush_interval = self.flush_interval;
+        let mut stopped = self.stopped.subscribe();
+
+        *flush_task = Some(tokio::spawn(async move {
+            while !*stopped.borrow_and_update() {
+                // Only the wait is cut short by stop; a flush under way finishes
+                tokio::select! {
+                    _ = sleep(flush_interval) => {
+                        let _ = flush(&*storage, &pending).await;
+                    }
+                    _ = stopped.changed() => {}
+                }
+            }
+        }));
+    }
+
+    /// Authenticates a default-format key, buffering its use for the next flush
+    ///
+    /// Works like [`authenticate`](crate::authenticate) against the tracker's
+    /// storage, and also records the use, which storage hears about when the
+    /// tracker flushes. This is the entry point for services that track usage.
+    pub async fn authenticate(&self, key: &str, ip: Option<IpAddr>) -> AuthResult<AuthenticatedKey> {
+        self.authenticate_with_format(key, &KeyFormat::default(), ip).await
+    }
+
+    /// Authenticates a key issued in a custom [`KeyFormat`], buffering its use
+    pub async fn authenticate_with_format(
+        &self,
+        key: &str,
+        format: &KeyFormat,
+        ip: Option<IpAddr>,
+    ) -> AuthResult<AuthenticatedKey> {
+        let parsed = format.parse(key).map_err(|_| ApiKeyError::InvalidFormat)?;
+        self.authenticate_parsed(&parsed, ip).await
+    }
+
+    /// Authenticates a key that has already been parsed, buffering its use
+    ///
+    /// Legacy keys authenticate this way, wrapped with [`ParsedApiKey::legacy`].
+    pub async fn authenticate_parsed(&self, key: &ParsedApiKey, ip: Option<IpAddr>) -> AuthResult<AuthenticatedKey> {
+        let mut authenticated = authenticate_parsed(&*self.storage, key).await?;
+
+        // The returned metadata reflects the use, as storage will once it is flushed
+        let usage = KeyUsage::new(key.lookup_id(), Utc::now(), ip);
+        apply_usage(&mut authenticated.metadata, &usage);
+        merge_into(&mut self.pending.lock().unwrap(), usage);
+        Ok(authenticated)
+    }
+
+    /// Stops periodic flushing and writes out whatever is still buffered
+    ///
+    /// Waits for the periodic flush task to exit first, so no flush of its
+    /// own is still writing when this returns.
+    pub async fn stop(&self) -> Result<usize, StorageError> {
+        self.stopped.send_replace(true);
+        let flush_task = self.flush_task.lock().unwrap().take();
+        if let Some(flush_task) = flush_task {
+            let _ = flush_task.await;
+        }
+        self.flush().await
+    }
+}
+
+fn merge_into(pending: &mut HashMap<String, KeyUsage>, usage: KeyUsage) {
+    match pending.get_mut(&usage.lookup_id) {
+        Some(existing) => existing.merge(usage),
+        None => {
+            pending.insert(usage.lookup_id.clone(), usage);
+        }
+    }
+}
+
+async fn flush(
+    storage: &dyn ApiKeyStorage,
+    pending: &Mutex<HashMap<String, KeyUsage>>,
+) -> Result<usize, StorageError> {
+    let batch: Vec<KeyUsage> = mem::take(&mut *pending.lock().unwrap()).into_values().collect();
+    if batch.is_empty() {
+        return Ok(0);
+    }
+
+    match storage.record_usage(&batch).await {
+        Ok(()) => Ok(batch.len()),
+        Err(e) => {
+            let mut pending = pending.lock().unwrap();
+            for usage in batch {
+                merge_into(&mut pending, usage);
+            }
+            Err(e)
+        }
+    }
+}
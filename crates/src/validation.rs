@@ -1,7 +1,11 @@
+use std::fmt;
+use std::net::IpAddr;
 use thiserror::Error;
 use chrono::{DateTime, Utc};
-use crate::generation::{Environment, validate_key_format, KeyGenerationError};
-use crate::hashing::{KeyHash, HashingError};
+use crate::generation::{Environment, KeyFormat, KeyKind, LegacyKeys, ParsedApiKey};
+use crate::hashing::{Argon2Hasher, KeyHash, KeyHasher, HashingError};
+use crate::authorization::Scope;
+use crate::request::IpPolicy;
 
 #[derive(Error, Debug)]
 pub enum ApiKeyValidationError {
@@ -23,29 +27,89 @@ pub enum ApiKeyValidationError {
     HashVerificationFailed,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct ApiKeyMetadata {
     pub created_at: DateTime<Utc>,
     pub last_used_at: Option<DateTime<Utc>>,
     pub expires_at: Option<DateTime<Utc>>,
     pub environment: Environment,
+    pub lookup_id: String, // Non-secret identifier storage indexes on
     pub is_active: bool,
     pub is_revoked: bool,
     pub key_hash: String, // Store serialized hash
+    #[serde(default)]
+    pub ip_policy: IpPolicy, // Networks this key may be used from
+    #[serde(default = "Scope::all")]
+    pub scopes: Vec<Scope>, // Permissions granted to the key
+    #[serde(default)]
+    pub kind: KeyKind, // Secret or publishable
+    #[serde(default)]
+    pub paired_key: Option<String>, // Lookup id of the key this one was issued alongside
+    #[serde(default)]
+    pub request_count: u64, // Successful authentications recorded so far
+    #[serde(default)]
+    pub last_used_ip: Option<IpAddr>, // Client address of the most recent recorded use
+}
+
+// The hash is kept out of logs so it can't be fed to an offline cracker
+impl fmt::Debug for ApiKeyMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKeyMetadata")
+            .field("created_at", &self.created_at)
+            .field("last_used_at", &self.last_used_at)
+            .field("expires_at", &self.expires_at)
+            .field("environment", &self.environment)
+            .field("lookup_id", &self.lookup_id)
+            .field("is_active", &self.is_active)
+            .field("is_revoked", &self.is_revoked)
+            .field("key_hash", &"<redacted>")
+            .field("ip_policy", &self.ip_policy)
+            .field("scopes", &self.scopes)
+            .field("kind", &self.kind)
+            .field("paired_key", &self.paired_key)
+            .field("request_count", &self.request_count)
+            .field("last_used_ip", &self.last_used_ip)
+            .finish()
+    }
 }
 
 impl ApiKeyMetadata {
-    pub fn new(environment: Environment, key: &str) -> Result<Self, HashingError> {
-        let key_hash = KeyHash::new(key)?;
-        Ok(Self {
+    pub fn new(key: &ParsedApiKey) -> Result<Self, HashingError> {
+        Self::with_hasher(key, &Argon2Hasher::default())
+    }
+
+    /// Creates metadata whose hash is made by `hasher`
+    pub fn with_hasher(key: &ParsedApiKey, hasher: &dyn KeyHasher) -> Result<Self, HashingError> {
+        let key_hash = hasher.hash(key.expose_secret())?;
+        Ok(Self::with_hash(key.environment(), key.kind(), key.lookup_id().to_string(), key_hash))
+    }
+
+    /// Builds metadata for a key known only by the SHA-256 digest the legacy store.json kept
+    ///
+    /// The key is indexed on the id `legacy` derives from the digest, which
+    /// [`ParsedApiKey::legacy`] derives again from the key itself.
+    pub fn from_legacy_digest(legacy: &LegacyKeys, digest: &str) -> Result<Self, HashingError> {
+        let key_hash = KeyHash::from_legacy_digest(digest)?;
+        Ok(Self::with_hash(legacy.environment(), KeyKind::Secret, legacy.lookup_id(digest), key_hash))
+    }
+
+    fn with_hash(environment: Environment, kind: KeyKind, lookup_id: String, key_hash: KeyHash) -> Self {
+        Self {
             created_at: Utc::now(),
             last_used_at: None,
             expires_at: None,
             environment,
+            lookup_id,
             is_active: true,
             is_revoked: false,
             key_hash: key_hash.to_string(),
-        })
+            ip_policy: IpPolicy::default(),
+            scopes: kind.default_scopes(),
+            kind,
+            paired_key: None,
+            request_count: 0,
+            last_used_ip: None,
+        }
     }
 
     pub fn is_valid(&self) -> bool {
@@ -61,8 +125,33 @@ impl ApiKeyMetadata {
     }
 
     pub fn verify_key(&self, key: &str) -> Result<bool, HashingError> {
+        self.verify_key_with(key, &Argon2Hasher::default())
+    }
+
+    /// Verifies `key` through `hasher`, which is needed for peppered hashes
+    pub fn verify_key_with(&self, key: &str, hasher: &dyn KeyHasher) -> Result<bool, HashingError> {
         let key_hash = KeyHash::from_string(&self.key_hash)?;
-        key_hash.verify(key)
+        hasher.verify(&key_hash, key)
+    }
+
+    /// Re-hashes an already verified `key` if its stored hash isn't what `hasher` would make
+    ///
+    /// A hash still in the `salt:hash` form is at least rewritten as a PHC string.
+    ///
+    /// # Returns
+    /// * `Result<bool, HashingError>` - Whether `key_hash` was replaced
+    pub fn rehash_if_needed(&mut self, key: &str, hasher: &dyn KeyHasher) -> Result<bool, HashingError> {
+        let key_hash = KeyHash::from_string(&self.key_hash)?;
+        if hasher.needs_rehash(&key_hash)? {
+            self.key_hash = hasher.hash(key)?.to_string();
+            return Ok(true);
+        }
+
+        if KeyHash::is_legacy_encoding(&self.key_hash) {
+            self.key_hash = key_hash.to_string();
+            return Ok(true);
+        }
+        Ok(false)
     }
 }
 
@@ -75,32 +164,69 @@ impl ApiKeyMetadata {
 /// # Returns
 /// * `Result<(), ApiKeyValidationError>` - Ok if valid, error if invalid
 pub fn validate_api_key(key: &str, metadata: &ApiKeyMetadata) -> Result<(), ApiKeyValidationError> {
-    // First validate the format and environment
-    match validate_key_format(key, Some(metadata.environment)) {
-        Ok(_) => {}
-        Err(KeyGenerationError::InvalidFormat) => {
-            // Check if it's an environment mismatch
-            let key_env = if key.starts_with("tronch_sk_test_") {
-                Environment::Test
-            } else if key.starts_with("tronch_sk_live_") {
-                Environment::Live
-            } else {
-                return Err(ApiKeyValidationError::InvalidFormat);
-            };
-
-            if key_env != metadata.environment {
-                return Err(ApiKeyValidationError::EnvironmentMismatch);
-            }
-            return Err(ApiKeyValidationError::InvalidFormat);
-        }
-        Err(_) => return Err(ApiKeyValidationError::InvalidFormat),
+    validate_api_key_with_format(key, metadata, &KeyFormat::default())
+}
+
+/// Validates an API key issued in a custom [`KeyFormat`]
+pub fn validate_api_key_with_format(
+    key: &str,
+    metadata: &ApiKeyMetadata,
+    format: &KeyFormat,
+) -> Result<(), ApiKeyValidationError> {
+    let parsed = format.parse(key).map_err(|_| ApiKeyValidationError::InvalidFormat)?;
+    validate_parsed_api_key(&parsed, metadata)
+}
+
+/// Validates a key that has already been parsed against its metadata
+pub fn validate_parsed_api_key(
+    parsed: &ParsedApiKey,
+    metadata: &ApiKeyMetadata,
+) -> Result<(), ApiKeyValidationError> {
+    validate_parsed_api_key_with_hasher(parsed, metadata, &Argon2Hasher::default())
+}
+
+/// Validates a parsed key whose hash was made by `hasher`
+pub fn validate_parsed_api_key_with_hasher(
+    parsed: &ParsedApiKey,
+    metadata: &ApiKeyMetadata,
+    hasher: &dyn KeyHasher,
+) -> Result<(), ApiKeyValidationError> {
+    validate_parsed(parsed, metadata, Some(hasher))
+}
+
+/// Validates a parsed key against metadata whose hash storage has already verified
+///
+/// [`ApiKeyStorage::get_metadata`](crate::storage::ApiKeyStorage::get_metadata)
+/// only returns metadata for a key that matched its hash, so this checks
+/// everything else without paying for a second verification.
+pub fn validate_verified_api_key(
+    parsed: &ParsedApiKey,
+    metadata: &ApiKeyMetadata,
+) -> Result<(), ApiKeyValidationError> {
+    validate_parsed(parsed, metadata, None)
+}
+
+fn validate_parsed(
+    parsed: &ParsedApiKey,
+    metadata: &ApiKeyMetadata,
+    hasher: Option<&dyn KeyHasher>,
+) -> Result<(), ApiKeyValidationError> {
+    if parsed.environment() != metadata.environment {
+        return Err(ApiKeyValidationError::EnvironmentMismatch);
+    }
+
+    // A publishable key must not pass for a secret one, or vice versa
+    if parsed.kind() != metadata.kind {
+        return Err(ApiKeyValidationError::InvalidFormat);
     }
 
     // Verify the key hash
-    match metadata.verify_key(key) {
-        Ok(true) => {},
-        Ok(false) => return Err(ApiKeyValidationError::InvalidFormat),
-        Err(_) => return Err(ApiKeyValidationError::HashVerificationFailed),
+    if let Some(hasher) = hasher {
+        match metadata.verify_key_with(parsed.expose_secret(), hasher) {
+            Ok(true) => {},
+            Ok(false) => return Err(ApiKeyValidationError::InvalidFormat),
+            Err(_) => return Err(ApiKeyValidationError::HashVerificationFailed),
+        }
     }
 
     // Check key status
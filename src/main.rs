@@ -1,3 +1,80 @@
+use std::env;
+use std::process;
+use std::time::Duration;
+use tronch::hashing::{HashingBenchmark, HashingConfig};
+
+const USAGE: &str = "Usage: api_gen calibrate [--target-ms <ms>] [--samples <n>] \
+                     [--memory-cost <KiB>] [--time-cost <n>] [--parallelism <n>]";
+
+/// Verify latency `calibrate` aims for unless told otherwise
+const DEFAULT_TARGET_MS: u64 = 50;
+
+/// Verifies timed per configuration unless told otherwise
+const DEFAULT_SAMPLES: usize = 20;
+
 fn main() {
-    println!("TRONCH API Management System");
-} 
\ No newline at end of file
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        None => {
+            println!("TRONCH API Management System");
+            Ok(())
+        }
+        Some("calibrate") => calibrate(&args[1..]),
+        Some(command) => Err(format!("Unknown command: {}", command)),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    }
+}
+
+/// Benchmarks the current hashing config and recommends one for the target latency
+///
+/// The current config is the default one, with any costs given on the
+/// command line in place of the defaults.
+fn calibrate(args: &[String]) -> Result<(), String> {
+    let mut target_ms = DEFAULT_TARGET_MS;
+    let mut samples = DEFAULT_SAMPLES;
+    let mut current = HashingConfig::default();
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--target-ms" => target_ms = value.parse().map_err(|_| format!("Invalid target: {}", value))?,
+            "--samples" => samples = value.parse().map_err(|_| format!("Invalid sample count: {}", value))?,
+            "--memory-cost" => {
+                current.memory_cost = value.parse().map_err(|_| format!("Invalid memory cost: {}", value))?
+            }
+            "--time-cost" => current.time_cost = value.parse().map_err(|_| format!("Invalid time cost: {}", value))?,
+            "--parallelism" => {
+                current.parallelism = value.parse().map_err(|_| format!("Invalid parallelism: {}", value))?
+            }
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+
+    println!("Current config:");
+    report(&current.benchmark(samples).map_err(|e| e.to_string())?);
+
+    let target = Duration::from_millis(target_ms);
+    println!("\nCalibrating for a {} ms verify...", target_ms);
+    let recommended = current.calibrate(target, samples).map_err(|e| e.to_string())?;
+    println!("Recommended config:");
+    report(&recommended.benchmark(samples).map_err(|e| e.to_string())?);
+
+    Ok(())
+}
+
+fn report(benchmark: &HashingBenchmark) {
+    let config = &benchmark.config;
+    println!("  algorithm:   {:?}", config.algorithm);
+    println!("  memory_cost: {} KiB", config.memory_cost);
+    println!("  time_cost:   {}", config.time_cost);
+    println!("  parallelism: {}", config.parallelism);
+    println!("  p50 verify:  {:.2} ms", benchmark.p50.as_secs_f64() * 1000.0);
+    println!("  p99 verify:  {:.2} ms", benchmark.p99.as_secs_f64() * 1000.0);
+    println!("  throughput:  {:.1} verifies/s per core", benchmark.throughput);
+}